@@ -0,0 +1,155 @@
+use std::collections::BTreeSet;
+
+use crate::shelf::*;
+
+/// Render the shelf as BibTeX entries, one `@book` per item. Items whose keys
+/// collide get a `-2`, `-3`... suffix, in the order of the index.
+pub fn export_bibtex(shelf: &Shelf) -> String {
+    let mut entries: Vec<String> = Vec::new();
+    let mut keys: BTreeSet<String> = BTreeSet::new();
+    for ((module, code), item) in shelf.get_index().iter() {
+        let base = citation_key(module, code);
+        let mut key = base.clone();
+        let mut n = 1;
+        while keys.contains(&key) {
+            n += 1;
+            key = format!("{}-{}", base, n);
+        }
+        entries.push(entry(&key, module, code, item));
+        keys.insert(key);
+    }
+    entries.join("\n")
+}
+
+/// Render an item as a BibTeX `@book` entry
+pub fn bibtex_entry(module: &str, code: &str, item: &Item) -> String {
+    entry(&citation_key(module, code), module, code, item)
+}
+
+fn entry(key: &str, module: &str, code: &str, item: &Item) -> String {
+    let (title, authors, genres) = item.export();
+    let mut entry = format!("@book{{{},\n", key);
+    entry.push_str(&format!("  title = {{{{{}}}}},\n", escape(title)));
+    if !authors.is_empty() {
+        let authors: Vec<String> = authors.iter().map(|a| escape(a)).collect();
+        entry.push_str(&format!("  author = {{{}}},\n", authors.join(" and ")));
+    }
+    if !genres.is_empty() {
+        let genres: Vec<String> = genres.iter().map(|g| escape(g)).collect();
+        entry.push_str(&format!("  keywords = {{{}}},\n", genres.join(", ")));
+    }
+    entry.push_str(&format!(
+        "  note = {{{} {}}},\n",
+        escape(module),
+        escape(code)
+    ));
+    entry.push_str("}\n");
+    entry
+}
+
+/// Format a plain text citation such as `Jane Smith and John Doe. Title.
+/// https://example.com/book/1234`
+pub fn citation(item: &Item, url: Option<&str>) -> String {
+    let (title, authors, _) = item.export();
+    let authors: Vec<&str> = authors.iter().map(String::as_str).collect();
+    let mut result = match authors.split_last() {
+        Some((last, [])) => format!("{}. ", last),
+        Some((last, rest)) => format!("{} and {}. ", rest.join(", "), last),
+        None => String::new(),
+    };
+    result.push_str(title);
+    if !title.ends_with(&['.', '?', '!'][..]) {
+        result.push('.');
+    }
+    if let Some(url) = url.filter(|u| !u.is_empty()) {
+        result.push(' ');
+        result.push_str(url);
+    }
+    result
+}
+
+/// Derive a citation key from an item's module and code, which stays the same
+/// across exports. Characters not allowed in keys are replaced with `-`, so
+/// different items may share a key.
+pub fn citation_key(module: &str, code: &str) -> String {
+    format!("{}:{}", module, code)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_:./".contains(c) {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Escape characters with a meaning in BibTeX and LaTeX
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '{' | '}' | '&' | '%' | '$' | '#' | '_' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '\\' => result.push_str("\\textbackslash{}"),
+            '~' => result.push_str("\\textasciitilde{}"),
+            '^' => result.push_str("\\textasciicircum{}"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shelf(items: &[(&str, &str, &str, &[&str])]) -> Shelf {
+        let mut shelf = Shelf::new();
+        for (module, code, title, authors) in items {
+            let authors = authors.iter().map(|a| a.to_string()).collect();
+            shelf.add_item(module, code, title.to_string(), authors, BTreeSet::new());
+        }
+        shelf
+    }
+
+    #[test]
+    fn renders_entries() {
+        let shelf = shelf(&[("mod", "1", "A & B_{c}", &["Jane Smith", "John Doe"])]);
+        assert_eq!(
+            "@book{mod:1,\n  title = {{A \\& B\\_\\{c\\}}},\n  author = {Jane Smith and John Doe},\n  note = {mod 1},\n}\n",
+            export_bibtex(&shelf)
+        );
+    }
+
+    #[test]
+    fn colliding_keys_get_suffixes() {
+        let shelf = shelf(&[
+            ("mod", "a b", "1", &[]),
+            ("mod", "a&b", "2", &[]),
+            ("mod", "a-b", "3", &[]),
+        ]);
+        let bibtex = export_bibtex(&shelf);
+        let keys: Vec<&str> = bibtex
+            .lines()
+            .filter_map(|l| l.strip_prefix("@book{"))
+            .collect();
+        assert_eq!(vec!["mod:a-b,", "mod:a-b-2,", "mod:a-b-3,"], keys);
+    }
+
+    #[test]
+    fn citations() {
+        let shelf = shelf(&[
+            ("mod", "1", "Title", &["A", "B", "C"]),
+            ("mod", "2", "Why?", &[]),
+        ]);
+        let item = |code| shelf.get_item("mod", code).unwrap();
+        assert_eq!(
+            "A, B and C. Title. https://example.com",
+            citation(item("1"), Some("https://example.com"))
+        );
+        assert_eq!("Why?", citation(item("2"), Some("")));
+    }
+}
@@ -0,0 +1,163 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::config::*;
+use crate::viewer::split_command;
+
+#[derive(Debug)]
+pub enum ClipboardError {
+    /// No command is configured, and OSC 52 is disabled or unavailable
+    Unavailable,
+    /// Every configured command failed, with the error of the last one
+    CommandFailed(String),
+    IoError(std::io::Error),
+}
+
+/// Which of OSC 52 escapes and clipboard commands is tried first
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipboardPriority {
+    Osc52,
+    Commands,
+}
+
+impl ClipboardPriority {
+    pub fn from_name(name: &str) -> Option<ClipboardPriority> {
+        match name {
+            "osc52" => Some(ClipboardPriority::Osc52),
+            "commands" => Some(ClipboardPriority::Commands),
+            _ => None,
+        }
+    }
+}
+
+/// Copy text to the clipboard. By default, OSC 52 escapes are used when enabled
+/// and writing to a terminal, which works over SSH and without an X clipboard,
+/// and the configured commands are tried in order otherwise. With the
+/// `commands` priority, the commands are tried first and OSC 52 is the
+/// fallback.
+pub fn copy(config: &Config, text: &str) -> Result<(), ClipboardError> {
+    let osc52 = config.osc52 && osc52_supported();
+    if osc52 && config.clipboard_priority == ClipboardPriority::Osc52 {
+        return write_osc52(text);
+    }
+    let mut result = Err(ClipboardError::Unavailable);
+    for command in config.clipboard_commands.iter() {
+        result = run_command(command, text);
+        if result.is_ok() {
+            return result;
+        }
+    }
+    if osc52 {
+        return write_osc52(text);
+    }
+    result
+}
+
+/// Check if stdout is a terminal that may understand OSC 52. The Linux console
+/// ignores it.
+fn osc52_supported() -> bool {
+    termion::is_tty(&std::io::stdout()) && std::env::var("TERM").map_or(true, |t| t != "linux")
+}
+
+fn write_osc52(text: &str) -> Result<(), ClipboardError> {
+    let mut sequence = format!("\x1b]52;c;{}\x07", base64::encode(text));
+    // tmux only passes escapes through to the outer terminal when wrapped
+    if std::env::var_os("TMUX").is_some() {
+        sequence = format!("\x1bPtmux;\x1b{}\x1b\\", sequence);
+    }
+    let mut stdout = std::io::stdout();
+    match stdout
+        .write_all(sequence.as_bytes())
+        .and_then(|()| stdout.flush())
+    {
+        Ok(()) => Ok(()),
+        Err(e) => Err(ClipboardError::IoError(e)),
+    }
+}
+
+/// Pipe text to a command such as `wl-copy` or `xclip -selection clipboard`
+fn run_command(command: &str, text: &str) -> Result<(), ClipboardError> {
+    let words = match split_command(command) {
+        Some(words) if !words.is_empty() => words,
+        _ => {
+            return Err(ClipboardError::CommandFailed(format!(
+                "invalid command {}",
+                command
+            )))
+        }
+    };
+    let (program, words) = (&words[0], &words[1..]);
+    let child = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            if let Some(mut stdin) = child.stdin.take() {
+                if let Err(e) = stdin.write_all(text.as_bytes()) {
+                    return Err(ClipboardError::IoError(e));
+                }
+            }
+            match child.wait() {
+                Ok(status) if status.success() => Ok(()),
+                Ok(status) => Err(ClipboardError::CommandFailed(format!(
+                    "{} exited with {}",
+                    program, status
+                ))),
+                Err(e) => Err(ClipboardError::IoError(e)),
+            }
+        }
+        Err(e) => Err(ClipboardError::CommandFailed(format!("{}: {}", program, e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(commands: &[&str]) -> Config {
+        Config {
+            osc52: false,
+            clipboard_commands: commands.iter().map(|c| c.to_string()).collect(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn first_working_command_is_used() {
+        let mut out = std::env::temp_dir();
+        out.push(format!("bookshelf-test-clipboard-{}", std::process::id()));
+        let command = format!("sh -c 'cat > \"$0\"' '{}'", out.display());
+        copy(&config(&["false", &command]), "mod 1").unwrap();
+        let copied = std::fs::read_to_string(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        assert_eq!("mod 1", copied);
+    }
+
+    #[test]
+    fn failures_are_reported() {
+        assert!(matches!(
+            copy(&config(&[]), ""),
+            Err(ClipboardError::Unavailable)
+        ));
+        assert!(matches!(
+            copy(&config(&["false"]), ""),
+            Err(ClipboardError::CommandFailed(_))
+        ));
+    }
+
+    #[test]
+    fn priorities_are_parsed() {
+        assert_eq!(
+            Some(ClipboardPriority::Commands),
+            ClipboardPriority::from_name("commands")
+        );
+        assert_eq!(
+            Some(ClipboardPriority::Osc52),
+            ClipboardPriority::from_name("osc52")
+        );
+        assert_eq!(None, ClipboardPriority::from_name("xclip"));
+    }
+}
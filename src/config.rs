@@ -1,7 +1,11 @@
-use serde_yaml::from_reader;
+use serde_yaml::{from_reader, Value};
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::path::PathBuf;
+use tui::layout::Constraint;
+
+use crate::clipboard::ClipboardPriority;
+use crate::tui::column::{default_columns, parse_width, Column};
 
 #[derive(Debug)]
 pub enum ConfigError {
@@ -13,10 +17,29 @@ pub struct Config {
     pub index_file: PathBuf,
     pub modules_dir: PathBuf,
     pub data_dir: PathBuf,
+    /// Commands used to open items, by media type
+    pub viewers: BTreeMap<String, String>,
+    /// Whether to copy with OSC 52 terminal escapes
+    pub osc52: bool,
+    /// Commands copying their input, tried in order
+    pub clipboard_commands: Vec<String>,
+    /// Whether OSC 52 or the commands are tried first
+    pub clipboard_priority: ClipboardPriority,
+    /// Columns of the TUI table, in order, with their widths
+    pub columns: Vec<(Column, Constraint)>,
+    /// Key sequences bound to TUI actions, replacing the default ones
+    pub keybindings: BTreeMap<String, Vec<String>>,
+    /// Built-in TUI theme under `name` and styles replacing its ones
+    pub theme: BTreeMap<String, String>,
+    /// How covers are drawn in the TUI: `auto`, `kitty`, `sixel`, `blocks` or
+    /// `none`
+    pub graphics: String,
+    /// Tabs of the TUI, each with its `name` and criteria of the items shown
+    pub tabs: Vec<BTreeMap<String, String>>,
 }
 
-impl Config {
-    pub fn default() -> Config {
+impl Default for Config {
+    fn default() -> Config {
         let config_root: PathBuf;
         if let Some(mut dir) = dirs_next::config_dir() {
             dir.push("bookshelf");
@@ -34,16 +57,30 @@ impl Config {
             index_file,
             modules_dir,
             data_dir,
+            viewers: BTreeMap::new(),
+            osc52: true,
+            clipboard_commands: vec![
+                String::from("wl-copy"),
+                String::from("xclip -selection clipboard"),
+            ],
+            clipboard_priority: ClipboardPriority::Osc52,
+            columns: default_columns(),
+            keybindings: BTreeMap::new(),
+            theme: BTreeMap::new(),
+            graphics: String::from("auto"),
+            tabs: Vec::new(),
         }
     }
+}
 
+impl Config {
     pub fn update(&mut self, config_file: &PathBuf) -> Result<(), ConfigError> {
         match File::open(config_file) {
             Ok(file) => {
-                let r: Result<BTreeMap<String, String>, serde_yaml::Error> = from_reader(file);
+                let r: Result<BTreeMap<String, Value>, serde_yaml::Error> = from_reader(file);
                 match r {
                     Ok(data) => {
-                        if let Some(index_file) = data.get("index_file") {
+                        if let Some(index_file) = data.get("index_file").and_then(Value::as_str) {
                             match shellexpand::full(index_file) {
                                 Ok(index_file) => {
                                     self.index_file = PathBuf::from(index_file.into_owned());
@@ -51,7 +88,7 @@ impl Config {
                                 Err(e) => println!("Error expanding path: {}", e),
                             }
                         }
-                        if let Some(modules_dir) = data.get("modules_dir") {
+                        if let Some(modules_dir) = data.get("modules_dir").and_then(Value::as_str) {
                             match shellexpand::full(modules_dir) {
                                 Ok(modules_dir) => {
                                     self.modules_dir = PathBuf::from(modules_dir.into_owned());
@@ -59,7 +96,7 @@ impl Config {
                                 Err(e) => println!("Error expanding path: {}", e),
                             }
                         }
-                        if let Some(data_dir) = data.get("data_dir") {
+                        if let Some(data_dir) = data.get("data_dir").and_then(Value::as_str) {
                             match shellexpand::full(data_dir) {
                                 Ok(data_dir) => {
                                     self.data_dir = PathBuf::from(data_dir.into_owned());
@@ -67,6 +104,113 @@ impl Config {
                                 Err(e) => println!("Error expanding path: {}", e),
                             }
                         }
+                        if let Some(viewers) = data.get("viewers").and_then(Value::as_mapping) {
+                            for (media_type, command) in viewers.iter() {
+                                match (media_type.as_str(), command.as_str()) {
+                                    (Some(media_type), Some(command)) => {
+                                        self.viewers
+                                            .insert(media_type.to_string(), command.to_string());
+                                    }
+                                    _ => println!("Invalid viewer: {:?}", media_type),
+                                }
+                            }
+                        }
+                        if let Some(osc52) = data.get("osc52").and_then(Value::as_bool) {
+                            self.osc52 = osc52;
+                        }
+                        if let Some(commands) =
+                            data.get("clipboard_commands").and_then(Value::as_sequence)
+                        {
+                            self.clipboard_commands = commands
+                                .iter()
+                                .filter_map(Value::as_str)
+                                .map(str::to_string)
+                                .collect();
+                        }
+                        if let Some(priority) =
+                            data.get("clipboard_priority").and_then(Value::as_str)
+                        {
+                            match ClipboardPriority::from_name(priority) {
+                                Some(priority) => self.clipboard_priority = priority,
+                                None => println!("Invalid clipboard priority: {}", priority),
+                            }
+                        }
+                        if let Some(columns) = data.get("columns").and_then(Value::as_sequence) {
+                            let columns: Vec<(Column, Constraint)> =
+                                columns.iter().filter_map(parse_column).collect();
+                            if !columns.is_empty() {
+                                self.columns = columns;
+                            }
+                        }
+                        if let Some(bindings) = data.get("keybindings").and_then(Value::as_mapping)
+                        {
+                            for (action, keys) in bindings.iter() {
+                                let keys: Vec<String> = match keys {
+                                    Value::String(keys) => vec![keys.clone()],
+                                    Value::Sequence(keys) => keys
+                                        .iter()
+                                        .filter_map(Value::as_str)
+                                        .map(str::to_string)
+                                        .collect(),
+                                    _ => Vec::new(),
+                                };
+                                match action.as_str() {
+                                    Some(action) => {
+                                        self.keybindings.insert(action.to_string(), keys);
+                                    }
+                                    None => println!("Invalid keybinding: {:?}", action),
+                                }
+                            }
+                        }
+                        match data.get("theme") {
+                            Some(Value::String(name)) => {
+                                self.theme.insert(String::from("name"), name.clone());
+                            }
+                            Some(Value::Mapping(styles)) => {
+                                for (key, style) in styles.iter() {
+                                    match (key.as_str(), style.as_str()) {
+                                        (Some(key), Some(style)) => {
+                                            self.theme.insert(key.to_string(), style.to_string());
+                                        }
+                                        _ => println!("Invalid theme entry: {:?}", key),
+                                    }
+                                }
+                            }
+                            Some(_) => println!("Invalid theme"),
+                            None => {}
+                        }
+                        if let Some(graphics) = data.get("graphics").and_then(Value::as_str) {
+                            self.graphics = graphics.to_string();
+                        }
+                        if let Some(tabs) = data.get("tabs").and_then(Value::as_sequence) {
+                            for tab in tabs.iter() {
+                                let criteria = match tab.as_mapping() {
+                                    Some(criteria) => criteria,
+                                    None => {
+                                        println!("Invalid tab: {:?}", tab);
+                                        continue;
+                                    }
+                                };
+                                let mut entries: BTreeMap<String, String> = BTreeMap::new();
+                                for (key, value) in criteria.iter() {
+                                    let value = match value {
+                                        Value::String(value) => value.clone(),
+                                        Value::Bool(value) => value.to_string(),
+                                        _ => {
+                                            println!("Invalid tab entry: {:?}", key);
+                                            continue;
+                                        }
+                                    };
+                                    match key.as_str() {
+                                        Some(key) => {
+                                            entries.insert(key.to_string(), value);
+                                        }
+                                        None => println!("Invalid tab entry: {:?}", key),
+                                    }
+                                }
+                                self.tabs.push(entries);
+                            }
+                        }
                         Ok(())
                     }
                     Err(e) => Err(ConfigError::YamlError(e)),
@@ -76,3 +220,62 @@ impl Config {
         }
     }
 }
+
+/// Parse a column of the TUI table, given either as its name or as a mapping
+/// from its name to its width
+fn parse_column(column: &Value) -> Option<(Column, Constraint)> {
+    let (name, width) = match column {
+        Value::String(name) => (name.as_str(), None),
+        Value::Mapping(m) if m.len() == 1 => {
+            let (name, width) = m.iter().next()?;
+            let width = match width {
+                Value::Number(n) => n.to_string(),
+                Value::String(s) => s.clone(),
+                _ => String::new(),
+            };
+            (name.as_str().unwrap_or(""), Some(width))
+        }
+        _ => ("", None),
+    };
+    let column = match Column::from_name(name) {
+        Some(column) => column,
+        None => {
+            println!("Invalid column: {:?}", column);
+            return None;
+        }
+    };
+    match width {
+        Some(width) => match parse_width(&width) {
+            Some(width) => Some((column, width)),
+            None => {
+                println!("Invalid width for column {}: {}", name, width);
+                Some((column, column.default_width()))
+            }
+        },
+        None => Some((column, column.default_width())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tabs() {
+        let file =
+            std::env::temp_dir().join(format!("bookshelf-test-{}-tabs.yaml", std::process::id()));
+        std::fs::write(
+            &file,
+            "tabs:\n  - {name: SF, genres: sf, favorites: true}\n  - unnamed\n",
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.update(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        // favorites are read as text, like other criteria
+        assert_eq!(1, config.tabs.len());
+        assert_eq!(Some(&String::from("SF")), config.tabs[0].get("name"));
+        assert_eq!(Some(&String::from("true")), config.tabs[0].get("favorites"));
+        assert_eq!(Some(&String::from("sf")), config.tabs[0].get("genres"));
+    }
+}
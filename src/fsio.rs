@@ -2,6 +2,11 @@ use bincode::{deserialize, serialize, ErrorKind};
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
+use crate::bibtex::export_bibtex;
+use crate::history::Change;
+use crate::html::export_html;
+use crate::markdown::{export_markdown, GroupBy};
+use crate::opds::export_opds;
 use crate::shelf::*;
 
 /// Create necessary directories if not already present.
@@ -14,35 +19,133 @@ pub fn create_dirs(data_dir: &PathBuf, modules_dir: &PathBuf) {
     }
 }
 
-/// Read the index file and returns a Shelf object.
-pub fn load_shelf(file: &PathBuf) -> Shelf {
-    if Path::new(&file).exists() {
-        let data: Vec<u8> = std::fs::read(file).expect("Unable to read index file");
-        let data: Result<Shelf, Box<ErrorKind>> = deserialize(&data);
-        match data {
-            Ok(d) => d,
-            Err(_e) => Shelf::new(),
+/// Recursively list the files in a directory, sorted by path
+pub fn list_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                files.extend(list_files(&path));
+            } else {
+                files.push(path);
+            }
         }
-    } else {
-        Shelf::new()
     }
+    files.sort();
+    files
 }
 
-/// Write Shelf object to index file
-pub fn save_shelf(shelf: &Shelf, index_file: &PathBuf) {
-    let data = serialize(&shelf);
-    match data {
+/// Guess the MIME type of a file from its extension
+pub fn mime_type(file: &Path) -> &'static str {
+    let ext = file
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .map(|e| e.to_lowercase());
+    match ext.as_deref() {
+        Some("epub") => "application/epub+zip",
+        Some("pdf") => "application/pdf",
+        Some("cbz") => "application/vnd.comicbook+zip",
+        Some("cbr") => "application/vnd.comicbook-rar",
+        Some("mobi") => "application/x-mobipocket-ebook",
+        Some("txt") => "text/plain",
+        Some("html") | Some("htm") => "text/html",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("mp3") => "audio/mpeg",
+        Some("mp4") => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Read the index file and returns a Shelf object.
+pub fn load_shelf(file: &Path) -> Shelf {
+    match read_shelf(file) {
+        Ok(shelf) => shelf,
+        Err(IndexError::IoError(e)) => panic!("Unable to read index file: {}", e),
+        Err(IndexError::BincodeError(_)) => Shelf::default(),
+    }
+}
+
+#[derive(Debug)]
+pub enum IndexError {
+    IoError(std::io::Error),
+    BincodeError(Box<ErrorKind>),
+}
+
+/// Read Shelf object from index file, or an empty Shelf if there is none.
+/// Unlike `load_shelf`, an unreadable index is an error rather than empty.
+pub fn read_shelf(file: &Path) -> Result<Shelf, IndexError> {
+    if !file.exists() {
+        return Ok(Shelf::new());
+    }
+    match std::fs::read(file) {
+        Ok(data) => deserialize(&data).map_err(IndexError::BincodeError),
+        Err(e) => Err(IndexError::IoError(e)),
+    }
+}
+
+/// Write Shelf object to index file, printing errors
+pub fn save_shelf(shelf: &Shelf, index_file: &Path) {
+    if let Err(e) = write_shelf(shelf, index_file) {
+        println!("Error writing index file: {:?}", e);
+    }
+}
+
+/// Write Shelf object to index file. The data is written to a temporary file
+/// which then replaces the index file, so the index is never left half-written.
+pub fn write_shelf(shelf: &Shelf, index_file: &Path) -> Result<(), IndexError> {
+    let data = serialize(&shelf).map_err(IndexError::BincodeError)?;
+    let mut tmp_file = index_file.to_path_buf().into_os_string();
+    tmp_file.push(".tmp");
+    std::fs::write(&tmp_file, data)
+        .and_then(|()| std::fs::rename(&tmp_file, index_file))
+        .map_err(IndexError::IoError)
+}
+
+/// Get the file storing the last change made from the command line, next to
+/// the index file
+fn change_file(index_file: &Path) -> PathBuf {
+    let mut file = index_file.to_path_buf().into_os_string();
+    file.push(".undo");
+    PathBuf::from(file)
+}
+
+/// Store the last change made from the command line so it can be undone
+pub fn save_change(change: &Change, index_file: &Path) {
+    match serialize(change) {
         Ok(d) => {
-            std::fs::write(index_file, d).expect("Unable to write to index file");
+            if let Err(e) = std::fs::write(change_file(index_file), d) {
+                println!("Error writing undo file: {}", e);
+            }
         }
-        Err(e) => {
-            println!("Error writing index file: {}", e)
+        Err(e) => println!("Error writing undo file: {}", e),
+    }
+}
+
+/// Forget the last change made from the command line, after a command which
+/// changed nothing so `bookshelf undo` doesn't reach past it
+pub fn clear_change(index_file: &Path) {
+    let file = change_file(index_file);
+    if file.exists() {
+        if let Err(e) = std::fs::remove_file(&file) {
+            println!("Error removing undo file: {}", e);
         }
-    };
+    }
+}
+
+/// Take the last change made from the command line, removing it from disk
+pub fn take_change(index_file: &Path) -> Option<Change> {
+    let file = change_file(index_file);
+    let data: Vec<u8> = std::fs::read(&file).ok()?;
+    let _ = std::fs::remove_file(&file);
+    deserialize(&data).ok()
 }
 
 pub fn import_shelf(shelf: &mut Shelf, index_file: &PathBuf) {
-    match File::open(&index_file) {
+    match File::open(index_file) {
         Ok(f) => {
             let data: Result<Shelf, serde_yaml::Error> = serde_yaml::from_reader(f);
             match data {
@@ -58,10 +161,102 @@ pub fn import_shelf(shelf: &mut Shelf, index_file: &PathBuf) {
     }
 }
 
-pub fn export_shelf(shelf: &Shelf, index_file: &PathBuf) {
-    let data = serde_yaml::to_string(&shelf).expect("Failed to export shelf");
-    match std::fs::write(index_file, data){
+/// Formats the shelf can be exported to
+pub enum ExportFormat {
+    Yaml,
+    Html,
+    Opds,
+    Markdown(GroupBy),
+    Bibtex,
+}
+
+impl ExportFormat {
+    /// Get the format from its name. group_by is only used by markdown.
+    pub fn from_name(name: &str, group_by: &str) -> Option<ExportFormat> {
+        match name {
+            "yaml" => Some(ExportFormat::Yaml),
+            "html" => Some(ExportFormat::Html),
+            "opds" => Some(ExportFormat::Opds),
+            "markdown" => GroupBy::from_name(group_by).map(ExportFormat::Markdown),
+            "bibtex" => Some(ExportFormat::Bibtex),
+            _ => None,
+        }
+    }
+}
+
+/// Export shelf to `out`, which is a file for yaml, markdown and bibtex, and a
+/// directory for html and opds.
+/// data_dir is used to link to downloaded files.
+pub fn export_shelf(shelf: &Shelf, data_dir: &Path, out: &Path, format: &ExportFormat) {
+    let result = match format {
+        ExportFormat::Yaml => {
+            let data = serde_yaml::to_string(&shelf).expect("Failed to export shelf");
+            std::fs::write(out, data)
+        }
+        ExportFormat::Html => export_html(shelf, data_dir, out),
+        ExportFormat::Opds => export_opds(shelf, data_dir, out),
+        ExportFormat::Markdown(group_by) => std::fs::write(out, export_markdown(shelf, group_by)),
+        ExportFormat::Bibtex => std::fs::write(out, export_bibtex(shelf)),
+    };
+    match result {
         Ok(()) => println!("Successfully exported index"),
-        Err(e) => println!("Error exporting index: {}", e)
+        Err(e) => println!("Error exporting index: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bookshelf-test-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn writes_and_reads_the_index() {
+        let index_file = temp_file("index");
+        assert_eq!(Shelf::new(), read_shelf(&index_file).unwrap());
+        let mut shelf = Shelf::new();
+        shelf.add_item(
+            "mod",
+            "1",
+            String::from("One"),
+            BTreeSet::new(),
+            BTreeSet::new(),
+        );
+        write_shelf(&shelf, &index_file).unwrap();
+        assert_eq!(shelf, read_shelf(&index_file).unwrap());
+        std::fs::write(&index_file, b"\xff").unwrap();
+        assert!(matches!(
+            read_shelf(&index_file),
+            Err(IndexError::BincodeError(_))
+        ));
+        std::fs::remove_file(&index_file).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_last_change() {
+        let index_file = temp_file("changes");
+        let before = Shelf::new();
+        let mut after = before.clone();
+        after.add_item(
+            "mod",
+            "1",
+            String::from("One"),
+            BTreeSet::new(),
+            BTreeSet::new(),
+        );
+        let change = Change::between("add", &before, &after).unwrap();
+
+        save_change(&change, &index_file);
+        assert_eq!("add", take_change(&index_file).unwrap().description());
+        // changes are undone once
+        assert!(take_change(&index_file).is_none());
+
+        save_change(&change, &index_file);
+        clear_change(&index_file);
+        assert!(take_change(&index_file).is_none());
+        clear_change(&index_file);
     }
 }
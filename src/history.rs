@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+
+use crate::shelf::*;
+
+/// A change to the shelf, with the state of the changed items before and
+/// after it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Change {
+    description: String,
+    before: Vec<ItemState>,
+    after: Vec<ItemState>,
+}
+
+impl Change {
+    /// Get the change from one version of a shelf to another. Returns None if
+    /// they are the same.
+    pub fn between(description: &str, before: &Shelf, after: &Shelf) -> Option<Change> {
+        let keys = before.changed_keys(after);
+        if keys.is_empty() {
+            return None;
+        }
+        Some(Change {
+            description: description.to_string(),
+            before: before.snapshot(&keys),
+            after: after.snapshot(&keys),
+        })
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Check if the changed items are still as the change left them, so it can
+    /// be reverted without losing later changes
+    pub fn is_current(&self, shelf: &Shelf) -> bool {
+        let keys: Vec<(String, String)> = self.after.iter().map(|s| s.key().clone()).collect();
+        shelf.snapshot(&keys) == self.after
+    }
+
+    pub fn revert(&self, shelf: &mut Shelf) {
+        for state in self.before.iter() {
+            shelf.restore(state);
+        }
+    }
+
+    pub fn apply(&self, shelf: &mut Shelf) {
+        for state in self.after.iter() {
+            shelf.restore(state);
+        }
+    }
+}
+
+/// Changes made to the shelf, which can be undone and redone
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+}
+
+impl History {
+    /// Record a change to some items, given their state from
+    /// `Shelf::snapshot` before the change. Changes not changing anything
+    /// aren't recorded.
+    pub fn record(&mut self, description: &str, before: Vec<ItemState>, shelf: &Shelf) {
+        let keys: Vec<(String, String)> = before.iter().map(|s| s.key().clone()).collect();
+        let after = shelf.snapshot(&keys);
+        if before != after {
+            self.undo.push(Change {
+                description: description.to_string(),
+                before,
+                after,
+            });
+            self.redo.clear();
+        }
+    }
+
+    /// Undo the last change, returning its description
+    pub fn undo(&mut self, shelf: &mut Shelf) -> Option<String> {
+        let change = self.undo.pop()?;
+        change.revert(shelf);
+        let description = change.description.clone();
+        self.redo.push(change);
+        Some(description)
+    }
+
+    /// Redo the last undone change, returning its description
+    pub fn redo(&mut self, shelf: &mut Shelf) -> Option<String> {
+        let change = self.redo.pop()?;
+        change.apply(shelf);
+        let description = change.description.clone();
+        self.undo.push(change);
+        Some(description)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelf::test_util::shelf;
+    use std::collections::BTreeSet;
+
+    fn key(module: &str, code: &str) -> (String, String) {
+        (module.to_string(), code.to_string())
+    }
+
+    #[test]
+    fn changed_keys() {
+        let before = shelf();
+        let mut after = before.clone();
+        assert!(before.changed_keys(&after).is_empty());
+        after.remove_item("a", "1");
+        after.set_favorite("b", "3", true);
+        after.add_item("c", "4", String::new(), BTreeSet::new(), BTreeSet::new());
+        assert_eq!(
+            vec![key("a", "1"), key("b", "3"), key("c", "4")],
+            before.changed_keys(&after)
+        );
+        assert!(Change::between("", &before, &before).is_none());
+    }
+
+    #[test]
+    fn undo_and_redo_round_trip() {
+        let original = shelf();
+        let mut shelf = original.clone();
+        let mut history = History::default();
+
+        let before = shelf.snapshot(&[key("a", "1"), key("b", "3")]);
+        shelf.remove_item("a", "1");
+        shelf.set_favorite("b", "3", true);
+        history.record("first", before, &shelf);
+        let first = shelf.clone();
+
+        let before = shelf.snapshot(&[key("b", "3")]);
+        shelf.edit_item(
+            Some("b"),
+            Some("3"),
+            Some("Children of Dune"),
+            None,
+            None,
+            false,
+        );
+        history.record("second", before, &shelf);
+        let second = shelf.clone();
+
+        // changes which change nothing aren't recorded
+        let before = shelf.snapshot(&[key("b", "3")]);
+        history.record("nothing", before, &shelf);
+
+        assert_eq!(Some(String::from("second")), history.undo(&mut shelf));
+        assert_eq!(first, shelf);
+        assert_eq!(Some(String::from("first")), history.undo(&mut shelf));
+        assert_eq!(original, shelf);
+        assert_eq!(None, history.undo(&mut shelf));
+        assert_eq!(Some(String::from("first")), history.redo(&mut shelf));
+        assert_eq!(Some(String::from("second")), history.redo(&mut shelf));
+        assert_eq!(second, shelf);
+        assert_eq!(None, history.redo(&mut shelf));
+
+        // a new change drops what could be redone
+        history.undo(&mut shelf);
+        let before = shelf.snapshot(&[key("b", "3")]);
+        shelf.remove_item("b", "3");
+        history.record("third", before, &shelf);
+        assert_eq!(None, history.redo(&mut shelf));
+    }
+
+    #[test]
+    fn changes_revert_only_when_current() {
+        let before = shelf();
+        let mut after = before.clone();
+        after.remove_item("a", "1");
+        let change = Change::between("rm", &before, &after).unwrap();
+        assert!(change.is_current(&after));
+        assert!(!change.is_current(&before));
+        change.revert(&mut after);
+        assert_eq!(before, after);
+        change.apply(&mut after);
+        assert!(!after.has_item("a", "1"));
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::fsio::list_files;
+use crate::shelf::*;
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border-bottom: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+th { cursor: pointer; user-select: none; }
+input { margin-bottom: 1em; padding: 0.3em; width: 30em; }
+";
+
+/// Sorts the table when a header is clicked and hides rows not matching the
+/// filter input
+const SCRIPT: &str = "const table = document.getElementById('index');
+const rows = Array.from(table.tBodies[0].rows);
+let sortColumn = -1;
+let ascending = true;
+table.tHead.querySelectorAll('th').forEach((th, column) => {
+  th.addEventListener('click', () => {
+    ascending = sortColumn === column ? !ascending : true;
+    sortColumn = column;
+    rows.sort((a, b) => {
+      const x = a.cells[column].textContent;
+      const y = b.cells[column].textContent;
+      return ascending ? x.localeCompare(y) : y.localeCompare(x);
+    });
+    rows.forEach(row => table.tBodies[0].appendChild(row));
+  });
+});
+document.getElementById('filter').addEventListener('input', event => {
+  const query = event.target.value.toLowerCase();
+  rows.forEach(row => {
+    row.hidden = !row.textContent.toLowerCase().includes(query);
+  });
+});
+";
+
+/// Generate a static HTML catalog of the shelf in out_dir, with an index page,
+/// a page per author and genre, and a page per item linking to its files under
+/// data_dir
+pub fn export_html(shelf: &Shelf, data_dir: &Path, out_dir: &Path) -> std::io::Result<()> {
+    let index = shelf.get_index();
+    let data_dir = data_dir
+        .canonicalize()
+        .unwrap_or_else(|_| data_dir.to_path_buf());
+    let mut authors: BTreeMap<&String, BTreeSet<&(String, String)>> = BTreeMap::new();
+    let mut genres: BTreeMap<&String, BTreeSet<&(String, String)>> = BTreeMap::new();
+    for (key, item) in index.iter() {
+        let (_, i_authors, i_genres) = item.export();
+        for author in i_authors {
+            authors.entry(author).or_default().insert(key);
+        }
+        for genre in i_genres {
+            genres.entry(genre).or_default().insert(key);
+        }
+    }
+
+    for dir in ["authors", "genres", "items"].iter() {
+        std::fs::create_dir_all(out_dir.join(dir))?;
+    }
+
+    // index
+    let keys: BTreeSet<&(String, String)> = index.keys().collect();
+    let body = format!(
+        "<h1>Bookshelf</h1>\n<p>{} items</p>\n<input id=\"filter\" type=\"search\" placeholder=\"Filter\">\n{}<script>\n{}</script>\n",
+        index.len(),
+        item_table(shelf, &keys, ""),
+        SCRIPT
+    );
+    write_page(&out_dir.join("index.html"), "Bookshelf", &body)?;
+
+    // authors and genres
+    for (kind, groups) in [("authors", &authors), ("genres", &genres)].iter() {
+        for (name, keys) in groups.iter() {
+            let body = format!(
+                "<p><a href=\"../index.html\">Index</a></p>\n<h1>{}</h1>\n{}",
+                escape(name),
+                item_table(shelf, keys, "../")
+            );
+            let page = out_dir.join(kind).join(format!("{}.html", slug(name)));
+            write_page(&page, name, &body)?;
+        }
+    }
+
+    // items
+    for ((module, code), item) in index.iter() {
+        let (title, i_authors, i_genres) = item.export();
+        let mut body = format!(
+            "<p><a href=\"../../index.html\">Index</a></p>\n<h1>{}</h1>\n<dl>\n",
+            escape(title)
+        );
+        body.push_str(&format!(
+            "<dt>Authors</dt><dd>{}</dd>\n",
+            links(i_authors, "../../authors/")
+        ));
+        body.push_str(&format!(
+            "<dt>Genres</dt><dd>{}</dd>\n",
+            links(i_genres, "../../genres/")
+        ));
+        body.push_str(&format!("<dt>Module</dt><dd>{}</dd>\n", escape(module)));
+        body.push_str(&format!("<dt>Code</dt><dd>{}</dd>\n", escape(code)));
+        if shelf.is_favorite(module, code) {
+            body.push_str("<dt>Favorite</dt><dd>Yes</dd>\n");
+        }
+        body.push_str("</dl>\n<h2>Files</h2>\n");
+        let mut item_dir = data_dir.clone();
+        item_dir.push(module);
+        item_dir.push(code);
+        let files = list_files(&item_dir);
+        if files.is_empty() {
+            body.push_str("<p>Not downloaded</p>\n");
+        } else {
+            body.push_str("<ul>\n");
+            for file in files {
+                let name = file.strip_prefix(&item_dir).unwrap_or(&file);
+                body.push_str(&format!(
+                    "<li><a href=\"{}\">{}</a></li>\n",
+                    escape(&file_url(&file)),
+                    escape(&name.to_string_lossy())
+                ));
+            }
+            body.push_str("</ul>\n");
+        }
+        let mut page = out_dir.join("items").join(slug(module));
+        std::fs::create_dir_all(&page)?;
+        page.push(format!("{}.html", slug(code)));
+        write_page(&page, title, &body)?;
+    }
+    Ok(())
+}
+
+fn write_page(file: &Path, title: &str, body: &str) -> std::io::Result<()> {
+    let page = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        body
+    );
+    std::fs::write(file, page)
+}
+
+/// Render a table of items, with links relative to `root`
+fn item_table(shelf: &Shelf, keys: &BTreeSet<&(String, String)>, root: &str) -> String {
+    let mut table = String::from(
+        "<table id=\"index\">\n<thead><tr><th>Title</th><th>Authors</th><th>Genres</th><th>Module</th><th>Code</th></tr></thead>\n<tbody>\n",
+    );
+    for (module, code) in keys {
+        if let Some(item) = shelf.get_item(module, code) {
+            let (title, authors, genres) = item.export();
+            table.push_str(&format!(
+                "<tr><td><a href=\"{}items/{}/{}.html\">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                root,
+                slug(module),
+                slug(code),
+                escape(title),
+                links(authors, &format!("{}authors/", root)),
+                links(genres, &format!("{}genres/", root)),
+                escape(module),
+                escape(code)
+            ));
+        }
+    }
+    table.push_str("</tbody>\n</table>\n");
+    table
+}
+
+/// Render comma-separated links to the author or genre pages in `dir`
+fn links(names: &BTreeSet<String>, dir: &str) -> String {
+    names
+        .iter()
+        .map(|n| format!("<a href=\"{}{}.html\">{}</a>", dir, slug(n), escape(n)))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Escape text for use in HTML content and attributes
+pub fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Turn a name into a string safe to use as a file name and in URLs. Bytes
+/// other than ASCII letters, digits and `-` are written as `_` followed by
+/// their hex value, so different names never share a slug.
+pub fn slug(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' {
+            result.push(b as char);
+        } else {
+            result.push_str(&format!("_{:02x}", b));
+        }
+    }
+    result
+}
+
+/// Get the name a slug was made from
+pub fn unslug(slug: &str) -> Option<String> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(slug.len());
+    let mut iter = slug.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'_' {
+            let hex = [iter.next()?, iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Get a `file://` URL for an absolute path, percent-encoding as needed
+pub fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            url.push(b as char);
+        } else {
+            url.push_str(&format!("%{:02X}", b));
+        }
+    }
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelf::test_util::set;
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;",
+            escape("<a href=\"x\">&'")
+        );
+    }
+
+    #[test]
+    fn slugs_round_trip() {
+        for name in ["John Doe", "a/b", "a_b", "Ünïcode", "x-1"].iter() {
+            let s = slug(name);
+            assert!(s
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_".contains(&b)));
+            assert_eq!(Some(name.to_string()), unslug(&s));
+        }
+        assert_ne!(slug("a b"), slug("a_b"));
+        assert_eq!(None, unslug("_z"));
+    }
+
+    #[test]
+    fn file_urls_are_encoded() {
+        assert_eq!(
+            "file:///data/a%20b/%23.epub",
+            file_url(Path::new("/data/a b/#.epub"))
+        );
+    }
+
+    #[test]
+    fn exports_pages() {
+        let mut shelf = Shelf::new();
+        shelf.add_item(
+            "mod",
+            "1",
+            String::from("<Title>"),
+            set(&["John Doe"]),
+            set(&["Sci-Fi"]),
+        );
+        let mut out = std::env::temp_dir();
+        out.push(format!("bookshelf-test-html-{}", std::process::id()));
+        export_html(&shelf, Path::new("/nonexistent"), &out).unwrap();
+        let index = std::fs::read_to_string(out.join("index.html")).unwrap();
+        let item = std::fs::read_to_string(out.join("items/mod/1.html")).unwrap();
+        let author = out
+            .join("authors")
+            .join(format!("{}.html", slug("John Doe")));
+        let genre = out.join("genres/Sci-Fi.html");
+        let (author, genre) = (author.exists(), genre.exists());
+        std::fs::remove_dir_all(&out).unwrap();
+        assert!(index.contains("<p>1 items</p>"));
+        assert!(index.contains("<a href=\"items/mod/1.html\">&lt;Title&gt;</a>"));
+        assert!(item.contains("<p>Not downloaded</p>"));
+        assert!(author && genre);
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::local_file;
+use crate::module_handler::*;
+use crate::shelf::*;
+
+/// Derive the directory for an item
+/// # Example:
+/// ```
+/// let data_root: String = String::from("/tmp/data");
+/// let module: &str = "myMod";
+/// let code: &str = "12345";
+/// let out_dir: String = get_item_dir(data_root, module, code);
+///
+/// assert_eq!("/tmp/data/myMod/12345", out_dir);
+/// ```
+pub fn get_item_dir(data_root: &Path, module: &str, code: &str) -> PathBuf {
+    let mut pb = data_root.to_path_buf();
+    pb.push(module);
+    pb.push(code);
+    pb
+}
+
+/// Given a URL, derive the module and code then add item to shelf, optionally
+/// downloading said item
+pub fn add_by_url(
+    shelf: &mut Shelf,
+    module_handler: &ModuleHandler,
+    url: &str,
+    data_root: Option<&PathBuf>,
+    verbose: bool,
+) -> Result<(), ModuleError> {
+    match module_handler.derive_module(url) {
+        Ok(module) => match module_handler.derive_code(module, url) {
+            Ok(code) => {
+                match add_by_code(
+                    shelf,
+                    module_handler,
+                    module,
+                    code.as_str(),
+                    data_root,
+                    verbose,
+                ) {
+                    Ok(()) => Ok(()),
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Print what a module printed while downloading, if anything
+pub fn print_output(out: String) {
+    if !out.is_empty() {
+        println!("{}", out);
+    }
+}
+
+/// Given a module and code, add item to shelf, optionally downloading said item
+pub fn add_by_code(
+    shelf: &mut Shelf,
+    module_handler: &ModuleHandler,
+    module: &str,
+    code: &str,
+    data_root: Option<&PathBuf>,
+    verbose: bool,
+) -> Result<(), ModuleError> {
+    if shelf.has_item(module, code) {
+        if verbose {
+            println!("Item {}/{} already indexed", &module, &code);
+        }
+        Ok(())
+    } else {
+        // Get metadata
+        match module_handler.get_metadata(module, code) {
+            // Print item (verbose)
+            Ok(metadata) => {
+                if verbose {
+                    println!(
+                        "Adding item: {}/{}\n\tTitle: {}\n\tAuthors: {}\n\tgenres: {}",
+                        &module, &code, &metadata.0, &metadata.1, metadata.2
+                    );
+                }
+                // title
+                let title = metadata.0;
+                // authors
+                let mut authors: BTreeSet<String> = BTreeSet::new();
+                for author in metadata.1.split(",") {
+                    authors.insert(author.to_string());
+                }
+                // genres
+                let mut genres: BTreeSet<String> = BTreeSet::new();
+                for genre in metadata.2.split(",") {
+                    genres.insert(genre.to_string());
+                }
+                // Construct item
+                shelf.add_item(module, code, title, authors, genres);
+                // Download if data_root is set
+                if let Some(data_root) = data_root {
+                    let dest_dir: PathBuf = get_item_dir(data_root, module, code);
+                    module_handler
+                        .download(module, code, &dest_dir)
+                        .map(print_output)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Given a path to a local EPUB, CBZ or PDF file, add it to shelf under the
+/// built-in local module and copy it into its item directory
+pub fn add_by_file(
+    shelf: &mut Shelf,
+    file: &Path,
+    data_root: &Path,
+    verbose: bool,
+) -> Result<(), ModuleError> {
+    let code = local_file::derive_code(file)?;
+    if shelf.has_item(local_file::LOCAL_MODULE, &code) {
+        if verbose {
+            println!(
+                "Item {}/{} already indexed",
+                local_file::LOCAL_MODULE,
+                &code
+            );
+        }
+        return Ok(());
+    }
+    let (title, authors, genres) = local_file::get_metadata(file)?;
+    if verbose {
+        println!(
+            "Adding item: {}/{}\n\tTitle: {}\n\tAuthors: {:?}\n\tgenres: {:?}",
+            local_file::LOCAL_MODULE,
+            &code,
+            &title,
+            &authors,
+            &genres
+        );
+    }
+    let dest_dir = get_item_dir(data_root, local_file::LOCAL_MODULE, &code);
+    local_file::copy_file(file, &dest_dir)?;
+    shelf.add_item(local_file::LOCAL_MODULE, &code, title, authors, genres);
+    Ok(())
+}
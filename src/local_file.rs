@@ -0,0 +1,337 @@
+use lopdf::{Document, Object};
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use zip::ZipArchive;
+
+use crate::module_handler::ModuleError;
+
+/// Name of the built-in module handling local files
+pub const LOCAL_MODULE: &str = "local";
+
+/// Get the code identifying a local file, which is its file name so that
+/// files differing only by extension don't collide
+pub fn derive_code(file: &Path) -> Result<String, ModuleError> {
+    match file.file_name().and_then(std::ffi::OsStr::to_str) {
+        Some(name) => Ok(name.to_string()),
+        None => Err(ModuleError::UnsupportedFile),
+    }
+}
+
+/// Get the media type of a local file, which is its lowercase extension
+pub fn get_media_type(file: &Path) -> Result<String, ModuleError> {
+    match file.extension().and_then(std::ffi::OsStr::to_str) {
+        Some(ext) => Ok(ext.to_lowercase()),
+        None => Err(ModuleError::UnsupportedFile),
+    }
+}
+
+/// Get the title, authors, and genres of an EPUB, CBZ or PDF file. The title
+/// falls back to the file stem if the file doesn't provide one.
+pub fn get_metadata(
+    file: &Path,
+) -> Result<(String, BTreeSet<String>, BTreeSet<String>), ModuleError> {
+    let (title, authors, genres) = match get_media_type(file)?.as_str() {
+        "epub" => epub_metadata(file)?,
+        "cbz" => cbz_metadata(file)?,
+        "pdf" => pdf_metadata(file)?,
+        _ => return Err(ModuleError::UnsupportedFile),
+    };
+    let title = match title {
+        Some(title) => title,
+        None => match file.file_stem().and_then(std::ffi::OsStr::to_str) {
+            Some(stem) => stem.to_string(),
+            None => return Err(ModuleError::UnsupportedFile),
+        },
+    };
+    Ok((title, authors, genres))
+}
+
+/// Copy a local file into its item directory, creating the directory if needed
+pub fn copy_file(file: &Path, dest_dir: &Path) -> Result<(), ModuleError> {
+    if let Err(e) = std::fs::create_dir_all(dest_dir) {
+        return Err(ModuleError::IoError(e));
+    }
+    match file.file_name() {
+        Some(name) => {
+            let mut dest: PathBuf = dest_dir.to_path_buf();
+            dest.push(name);
+            match std::fs::copy(file, dest) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(ModuleError::IoError(e)),
+            }
+        }
+        None => Err(ModuleError::UnsupportedFile),
+    }
+}
+
+type Metadata = (Option<String>, BTreeSet<String>, BTreeSet<String>);
+
+/// Read a file from a zip archive into a String
+fn read_zip_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String, ModuleError> {
+    match archive.by_name(name) {
+        Ok(mut entry) => {
+            let mut content = String::new();
+            match entry.read_to_string(&mut content) {
+                Ok(_) => Ok(content),
+                Err(e) => Err(ModuleError::IoError(e)),
+            }
+        }
+        Err(e) => Err(ModuleError::ZipError(e)),
+    }
+}
+
+fn open_zip(file: &Path) -> Result<ZipArchive<File>, ModuleError> {
+    match File::open(file) {
+        Ok(f) => match ZipArchive::new(f) {
+            Ok(archive) => Ok(archive),
+            Err(e) => Err(ModuleError::ZipError(e)),
+        },
+        Err(e) => Err(ModuleError::IoError(e)),
+    }
+}
+
+/// Get metadata from the OPF package document referenced by
+/// `META-INF/container.xml`
+fn epub_metadata(file: &Path) -> Result<Metadata, ModuleError> {
+    let mut archive = open_zip(file)?;
+    let container = read_zip_entry(&mut archive, "META-INF/container.xml")?;
+    let opf_path = match roxmltree::Document::parse(&container) {
+        Ok(doc) => doc
+            .descendants()
+            .find(|n| n.has_tag_name("rootfile"))
+            .and_then(|n| n.attribute("full-path"))
+            .map(|p| p.to_string()),
+        Err(e) => return Err(ModuleError::XmlError(e)),
+    };
+    let opf = match opf_path {
+        Some(p) => read_zip_entry(&mut archive, &p)?,
+        None => return Err(ModuleError::MalformedMetadata),
+    };
+    match roxmltree::Document::parse(&opf) {
+        Ok(doc) => {
+            let mut title: Option<String> = None;
+            let mut authors: BTreeSet<String> = BTreeSet::new();
+            let mut genres: BTreeSet<String> = BTreeSet::new();
+            for node in doc.descendants().filter(|n| n.is_element()) {
+                if let Some(text) = node.text().map(str::trim).filter(|t| !t.is_empty()) {
+                    match node.tag_name().name() {
+                        "title" if title.is_none() => title = Some(text.to_string()),
+                        "creator" => {
+                            authors.insert(text.to_string());
+                        }
+                        "subject" => {
+                            genres.insert(text.to_string());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Ok((title, authors, genres))
+        }
+        Err(e) => Err(ModuleError::XmlError(e)),
+    }
+}
+
+/// Get metadata from the `ComicInfo.xml` file of a comic book archive
+fn cbz_metadata(file: &Path) -> Result<Metadata, ModuleError> {
+    let mut archive = open_zip(file)?;
+    let mut name: Option<String> = None;
+    for i in 0..archive.len() {
+        if let Ok(entry) = archive.by_index(i) {
+            if entry.name().eq_ignore_ascii_case("ComicInfo.xml") {
+                name = Some(entry.name().to_string());
+                break;
+            }
+        }
+    }
+    let mut title: Option<String> = None;
+    let mut authors: BTreeSet<String> = BTreeSet::new();
+    let mut genres: BTreeSet<String> = BTreeSet::new();
+    // ComicInfo.xml is optional, so archives without one only get a title
+    if let Some(name) = name {
+        let info = read_zip_entry(&mut archive, &name)?;
+        match roxmltree::Document::parse(&info) {
+            Ok(doc) => {
+                let mut series: Option<String> = None;
+                let mut number: Option<String> = None;
+                for node in doc.root_element().children().filter(|n| n.is_element()) {
+                    if let Some(text) = node.text().map(str::trim).filter(|t| !t.is_empty()) {
+                        match node.tag_name().name() {
+                            "Title" => title = Some(text.to_string()),
+                            "Series" => series = Some(text.to_string()),
+                            "Number" => number = Some(text.to_string()),
+                            "Writer" | "Penciller" => authors.extend(split_list(text)),
+                            "Genre" | "Tags" => genres.extend(split_list(text)),
+                            _ => {}
+                        }
+                    }
+                }
+                // Issues often only have a series name and number
+                if title.is_none() {
+                    title = match (series, number) {
+                        (Some(s), Some(n)) => Some(format!("{} #{}", s, n)),
+                        (Some(s), None) => Some(s),
+                        _ => None,
+                    };
+                }
+            }
+            Err(e) => return Err(ModuleError::XmlError(e)),
+        }
+    }
+    Ok((title, authors, genres))
+}
+
+/// Get metadata from the Info dictionary of a PDF file
+fn pdf_metadata(file: &Path) -> Result<Metadata, ModuleError> {
+    let doc = match Document::load(file) {
+        Ok(doc) => doc,
+        Err(e) => return Err(ModuleError::PdfError(e)),
+    };
+    let mut title: Option<String> = None;
+    let mut authors: BTreeSet<String> = BTreeSet::new();
+    let mut genres: BTreeSet<String> = BTreeSet::new();
+    if let Ok(info) = doc
+        .trailer
+        .get_deref(b"Info", &doc)
+        .and_then(Object::as_dict)
+    {
+        let get = |key: &[u8]| {
+            info.get_deref(key, &doc)
+                .and_then(Object::as_str)
+                .ok()
+                .map(decode_pdf_string)
+                .filter(|s| !s.is_empty())
+        };
+        title = get(b"Title");
+        if let Some(author) = get(b"Author") {
+            authors.extend(split_list(&author));
+        }
+        if let Some(keywords) = get(b"Keywords") {
+            genres.extend(split_list(&keywords));
+        }
+    }
+    Ok((title, authors, genres))
+}
+
+/// Decode a PDF text string, which is either UTF-16BE with a byte order mark or
+/// PDFDocEncoding (treated as Latin-1)
+fn decode_pdf_string(bytes: &[u8]) -> String {
+    let s = if bytes.starts_with(&[0xfe, 0xff]) {
+        let units: Vec<u16> = bytes[2..]
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        bytes.iter().map(|&b| b as char).collect()
+    };
+    s.trim().to_string()
+}
+
+/// Split a comma or semicolon separated list, dropping empty entries
+fn split_list(list: &str) -> BTreeSet<String> {
+    list.split(&[',', ';'][..])
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    /// Write a zip archive with the given entries to a temporary file
+    fn write_zip(name: &str, entries: &[(&str, &str)]) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("bookshelf-test-{}-{}", std::process::id(), name));
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        for (entry, content) in entries {
+            zip.start_file(*entry, FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn code_keeps_extension() {
+        let epub = derive_code(Path::new("/tmp/book.epub")).unwrap();
+        let pdf = derive_code(Path::new("/tmp/book.pdf")).unwrap();
+        assert_eq!("book.epub", epub);
+        assert_ne!(epub, pdf);
+    }
+
+    #[test]
+    fn epub_metadata_from_opf() {
+        let file = write_zip(
+            "book.epub",
+            &[
+                (
+                    "META-INF/container.xml",
+                    r#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#,
+                ),
+                (
+                    "OEBPS/content.opf",
+                    r#"<package xmlns:dc="http://purl.org/dc/elements/1.1/"><metadata>
+                        <dc:title>A Title</dc:title>
+                        <dc:creator>First Author</dc:creator>
+                        <dc:creator>Second Author</dc:creator>
+                        <dc:subject>Fantasy</dc:subject>
+                    </metadata></package>"#,
+                ),
+            ],
+        );
+        let (title, authors, genres) = get_metadata(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!("A Title", title);
+        assert_eq!(2, authors.len());
+        assert!(authors.contains("Second Author"));
+        assert!(genres.contains("Fantasy"));
+    }
+
+    #[test]
+    fn cbz_title_from_series() {
+        let file = write_zip(
+            "issue.cbz",
+            &[(
+                "ComicInfo.xml",
+                "<ComicInfo><Series>Saga</Series><Number>3</Number>\
+                 <Writer>A; B</Writer><Genre>Drama, Space</Genre></ComicInfo>",
+            )],
+        );
+        let (title, authors, genres) = get_metadata(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!("Saga #3", title);
+        assert_eq!(2, authors.len());
+        assert_eq!(2, genres.len());
+    }
+
+    #[test]
+    fn cbz_without_info_uses_stem() {
+        let file = write_zip("untitled.cbz", &[("page.png", "")]);
+        let (title, authors, _) = get_metadata(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert!(title.ends_with("untitled"));
+        assert!(authors.is_empty());
+    }
+
+    #[test]
+    fn pdf_strings() {
+        assert_eq!("Title", decode_pdf_string(b" Title "));
+        assert_eq!("é", decode_pdf_string(&[0xfe, 0xff, 0x00, 0xe9]));
+        assert_eq!("caf\u{e9}", decode_pdf_string(b"caf\xe9"));
+    }
+
+    #[test]
+    fn lists_split_on_commas_and_semicolons() {
+        let list = split_list("a, b;c,, ");
+        assert_eq!(vec!["a", "b", "c"], list.into_iter().collect::<Vec<_>>());
+    }
+}
@@ -1,127 +1,36 @@
+pub mod bibtex;
+pub mod clipboard;
 pub mod config;
 pub mod fsio;
+pub mod history;
+pub mod html;
+pub mod items;
+pub mod local_file;
+pub mod markdown;
 pub mod module_handler;
+pub mod opds;
+pub mod server;
 pub mod shelf;
 pub mod tui;
+pub mod viewer;
 
 use clap::{load_yaml, App};
 use std::fs::File;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use std::{
-    collections::{BTreeMap, BTreeSet},
-    io::BufRead,
-};
+use std::{collections::BTreeMap, io::BufRead};
 
-use crate::tui::ui::TUI;
+use crate::tui::ui::{Exit, TUI};
 use config::*;
 use fsio::*;
+use history::Change;
+use items::*;
 use module_handler::*;
 use shelf::*;
 
-/// Derive the directory for an item
-/// # Example:
-/// ```
-/// let data_root: String = String::from("/tmp/data");
-/// let module: &str = "myMod";
-/// let code: &str = "12345";
-/// let out_dir: String = get_item_dir(data_root, module, code);
-///
-/// assert_eq!("/tmp/data/myMod/12345", out_dir);
-/// ```
-fn get_item_dir(data_root: &PathBuf, module: &str, code: &str) -> PathBuf {
-    let mut pb = data_root.clone();
-    pb.push(module);
-    pb.push(code);
-    pb
-}
-
-/// Given a URL, derive the module and code then add item to shelf, optionally
-/// downloading said item
-fn add_by_url(
-    shelf: &mut Shelf,
-    module_handler: &ModuleHandler,
-    url: &str,
-    data_root: Option<&PathBuf>,
-    verbose: bool,
-) -> Result<(), ModuleError> {
-    match module_handler.derive_module(url) {
-        Ok(module) => match module_handler.derive_code(module, url) {
-            Ok(code) => {
-                match add_by_code(
-                    shelf,
-                    &module_handler,
-                    module,
-                    code.as_str(),
-                    data_root,
-                    verbose,
-                ) {
-                    Ok(()) => Ok(()),
-                    Err(e) => Err(e),
-                }
-            }
-            Err(e) => Err(e),
-        },
-        Err(e) => Err(e),
-    }
-}
-
-/// Given a module and code, add item to shelf, optionally downloading said item
-fn add_by_code(
-    shelf: &mut Shelf,
-    module_handler: &ModuleHandler,
-    module: &str,
-    code: &str,
-    data_root: Option<&PathBuf>,
-    verbose: bool,
-) -> Result<(), ModuleError> {
-    if shelf.has_item(&module, &code) {
-        if verbose {
-            println!("Item {}/{} already indexed", &module, &code);
-        }
-        Ok(())
-    } else {
-        // Get metadata
-        match module_handler.get_metadata(&module, &code) {
-            // Print item (verbose)
-            Ok(metadata) => {
-                if verbose {
-                    println!(
-                        "Adding item: {}/{}\n\tTitle: {}\n\tAuthors: {}\n\tgenres: {}",
-                        &module, &code, &metadata.0, &metadata.1, metadata.2
-                    );
-                }
-                // title
-                let title = metadata.0;
-                // authors
-                let mut authors: BTreeSet<String> = BTreeSet::new();
-                for author in metadata.1.split(",") {
-                    authors.insert(author.to_string());
-                }
-                // genres
-                let mut genres: BTreeSet<String> = BTreeSet::new();
-                for genre in metadata.2.split(",") {
-                    genres.insert(genre.to_string());
-                }
-                // Construct item
-                shelf.add_item(&module, &code, title, authors, genres);
-                // Download if data_root is set
-                if let Some(data_root) = data_root {
-                    let dest_dir: PathBuf = get_item_dir(data_root, &module, &code);
-                    match module_handler.download(&module, &code, &dest_dir) {
-                        Ok(()) => Ok(()),
-                        Err(e) => Err(e),
-                    };
-                }
-                Ok(())
-            }
-            Err(e) => Err(e),
-        }
-    }
-}
-
 /// Add item to index and optionally download it.
 /// Set data_root to None to skip download, or set it to Some() to download
+#[allow(clippy::too_many_arguments)]
 fn add_item(
     shelf: &mut Shelf,
     module_handler: &ModuleHandler,
@@ -136,7 +45,7 @@ fn add_item(
     let mut errors: BTreeMap<String, ModuleError> = BTreeMap::new();
     if let Some(url) = url {
         // shelf add|download -u
-        match add_by_url(shelf, &module_handler, url, data_root, verbose) {
+        match add_by_url(shelf, module_handler, url, data_root, verbose) {
             Ok(()) => {
                 return Ok(());
             }
@@ -154,7 +63,7 @@ fn add_item(
                         Ok(url) => {
                             match add_by_url(
                                 shelf,
-                                &module_handler,
+                                module_handler,
                                 url.as_str(),
                                 data_root,
                                 verbose,
@@ -174,7 +83,7 @@ fn add_item(
     } else if let Some(module) = module {
         if let Some(code) = code {
             // shelf add|download -m MODULE -c CODE
-            match add_by_code(shelf, &module_handler, module, code, data_root, verbose) {
+            match add_by_code(shelf, module_handler, module, code, data_root, verbose) {
                 Ok(()) => {
                     return Ok(());
                 }
@@ -192,7 +101,7 @@ fn add_item(
                             Ok(code) => {
                                 match add_by_code(
                                     shelf,
-                                    &module_handler,
+                                    module_handler,
                                     module,
                                     code.as_str(),
                                     data_root,
@@ -240,7 +149,6 @@ fn cli_print_item(shelf: &Shelf, module: &str, code: &str) {
         genres.pop();
         genres.pop();
         println!("Authors: {}", genres);
-    } else {
     }
 }
 
@@ -264,7 +172,7 @@ fn main() {
             match &mut home_dir {
                 Some(h) => {
                     h.push(".config/bookshelf/bookshelf.yaml");
-                    match config.update(&h) {
+                    match config.update(h) {
                         Ok(()) => {}
                         Err(e) => println!("Error loading config file: {:?}", e),
                     }
@@ -281,6 +189,10 @@ fn main() {
     // These can be unwrap'd safely because load_config guarantees the entries
     let mut shelf: Shelf = load_shelf(&config.index_file);
     let module_handler = ModuleHandler::new(&config.modules_dir);
+    // Kept to record changes made by commands so they can be undone
+    let shelf_before = shelf.clone();
+    // Whether to write the shelf to the index file before exiting
+    let mut save = true;
 
     /***** main *****/
     match args.subcommand() {
@@ -290,24 +202,31 @@ fn main() {
             }
         }
         Some(("add", args)) => {
-            match add_item(
-                &mut shelf,
-                &module_handler,
-                args.value_of("url"),
-                args.value_of("url_file"),
-                args.value_of("module"),
-                args.value_of("code"),
-                args.value_of("code_file"),
-                None,
-                verbose,
-            ) {
-                Ok(()) => {
-                    println!("All items added sucessfully");
+            if let Some(file) = args.value_of("file") {
+                match add_by_file(&mut shelf, Path::new(file), &config.data_dir, verbose) {
+                    Ok(()) => println!("Item added sucessfully"),
+                    Err(e) => println!("{}: {:?}", file, e),
                 }
-                Err(errors) => {
-                    println!("Some items failed to be added:");
-                    for (item, error) in errors {
-                        println!("{}: {:?}", item, error);
+            } else {
+                match add_item(
+                    &mut shelf,
+                    &module_handler,
+                    args.value_of("url"),
+                    args.value_of("url_file"),
+                    args.value_of("module"),
+                    args.value_of("code"),
+                    args.value_of("code_file"),
+                    None,
+                    verbose,
+                ) {
+                    Ok(()) => {
+                        println!("All items added sucessfully");
+                    }
+                    Err(errors) => {
+                        println!("Some items failed to be added:");
+                        for (item, error) in errors {
+                            println!("{}: {:?}", item, error);
+                        }
                     }
                 }
             }
@@ -396,8 +315,11 @@ fn main() {
                     for (m, c) in result {
                         let dest_dir = get_item_dir(&config.data_dir, &m, &c);
                         match module_handler.download(&m[..], &c[..], &dest_dir) {
-                            Ok(()) => {}
-                            Err(_e) => println!("Module {} unavailable", &m),
+                            Ok(out) => print_output(out),
+                            Err(ModuleError::NoValidModule) => {
+                                println!("Module {} unavailable", &m)
+                            }
+                            Err(e) => println!("Error downloading {} {}: {:?}", &m, &c, e),
                         }
                     }
                 }
@@ -407,6 +329,42 @@ fn main() {
             }
         }
 
+        Some(("open", args)) => {
+            let module = args.value_of("module").unwrap();
+            let code = args.value_of("code").unwrap();
+            if shelf.has_item(module, code) {
+                match viewer::open_item(&config, &module_handler, module, code) {
+                    Ok(()) => {}
+                    Err(viewer::OpenError::NotDownloaded(dir)) => {
+                        println!(
+                            "{} {} is not downloaded ({} is empty)",
+                            module,
+                            code,
+                            dir.display()
+                        )
+                    }
+                    Err(e) => println!("Error opening {} {}: {:?}", module, code, e),
+                }
+            } else {
+                println!("Item {} {} not found", module, code);
+            }
+        }
+
+        Some(("undo", _args)) => match take_change(&config.index_file) {
+            Some(change) => {
+                if change.is_current(&shelf) {
+                    change.revert(&mut shelf);
+                    println!("Undid {}", change.description());
+                } else {
+                    println!(
+                        "Unable to undo {}: the items have changed since",
+                        change.description()
+                    );
+                }
+            }
+            None => println!("Nothing to undo"),
+        },
+
         Some(("info", args)) => {
             cli_print_item(
                 &shelf,
@@ -431,15 +389,57 @@ fn main() {
         }
 
         Some(("export", args)) => {
-            export_shelf(&shelf, &PathBuf::from(args.value_of("file").unwrap()));
+            // format and group_by have default values and are restricted to valid values
+            let format = ExportFormat::from_name(
+                args.value_of("format").unwrap(),
+                args.value_of("group_by").unwrap(),
+            )
+            .unwrap();
+            match shelf.search_item(
+                args.value_of("module"),
+                args.value_of("title"),
+                args.value_of("authors"),
+                args.value_of("genres"),
+                args.value_of("blacklist"),
+                args.is_present("broad_search"),
+                args.is_present("favorite"),
+            ) {
+                Ok(result) => {
+                    export_shelf(
+                        &shelf.subset(&result),
+                        &config.data_dir,
+                        // either the positional path or -f is required
+                        &PathBuf::from(args.value_of("path").or(args.value_of("file")).unwrap()),
+                        &format,
+                    );
+                }
+                Err(e) => {
+                    println!("Error exporting items: {}", e);
+                }
+            }
+        }
+
+        Some(("serve", args)) => {
+            // bind has a default value
+            if let Err(e) = server::serve(
+                &config,
+                &mut shelf,
+                &module_handler,
+                args.value_of("bind").unwrap(),
+            ) {
+                println!("Error: {}", e);
+            }
         }
 
         None => {
             // Start TUI if no argument is given
             let mut tui = TUI::new(&config, &mut shelf, &module_handler);
             match tui.start() {
-                Ok(()) => {}
+                Ok(Exit::Save) => {}
+                Ok(Exit::Discard) => save = false,
                 Err(e) => {
+                    // the shelf may have been left halfway through a change
+                    save = false;
                     println!("Error: {}", e)
                 }
             }
@@ -451,5 +451,15 @@ fn main() {
     }
 
     /***** Save and exit *****/
-    save_shelf(&shelf, &config.index_file);
+    if save {
+        save_shelf(&shelf, &config.index_file);
+    }
+    if let Some((name, _)) = args.subcommand() {
+        if ["add", "download", "rm", "edit", "import"].contains(&name) {
+            match Change::between(&format!("bookshelf {}", name), &shelf_before, &shelf) {
+                Some(change) => save_change(&change, &config.index_file),
+                None => clear_change(&config.index_file),
+            }
+        }
+    }
 }
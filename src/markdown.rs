@@ -0,0 +1,152 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::shelf::*;
+
+/// How items are grouped in a reading list
+pub enum GroupBy {
+    Author,
+    Genre,
+}
+
+impl GroupBy {
+    pub fn from_name(name: &str) -> Option<GroupBy> {
+        match name {
+            "author" => Some(GroupBy::Author),
+            "genre" => Some(GroupBy::Genre),
+            _ => None,
+        }
+    }
+}
+
+/// Render the shelf as a Markdown reading list, with a section per author or
+/// genre. Items appear in every section they belong to.
+pub fn export_markdown(shelf: &Shelf, group_by: &GroupBy) -> String {
+    let mut groups: BTreeMap<&String, BTreeSet<(&String, &String, &String)>> = BTreeMap::new();
+    let mut ungrouped: BTreeSet<(&String, &String, &String)> = BTreeSet::new();
+    for ((module, code), item) in shelf.get_index().iter() {
+        let (title, authors, genres) = item.export();
+        let names = match group_by {
+            GroupBy::Author => authors,
+            GroupBy::Genre => genres,
+        };
+        // Sort by title within groups
+        let entry = (title, module, code);
+        if names.is_empty() {
+            ungrouped.insert(entry);
+        }
+        for name in names {
+            groups.entry(name).or_default().insert(entry);
+        }
+    }
+
+    let mut result = String::from("# Reading list\n");
+    for (name, entries) in groups.iter() {
+        result.push_str(&format!("\n## {}\n\n", escape(name)));
+        for (_, module, code) in entries {
+            result.push_str(&list_entry(shelf, module, code));
+        }
+    }
+    if !ungrouped.is_empty() {
+        let heading = match group_by {
+            GroupBy::Author => "Unknown author",
+            GroupBy::Genre => "No genre",
+        };
+        result.push_str(&format!("\n## {}\n\n", heading));
+        for (_, module, code) in ungrouped {
+            result.push_str(&list_entry(shelf, module, code));
+        }
+    }
+    result
+}
+
+/// Render an item as a list entry, for example:
+/// `- **Title** by Author (genre, genre) ★ `module code``
+fn list_entry(shelf: &Shelf, module: &str, code: &str) -> String {
+    match shelf.get_item(module, code) {
+        Some(item) => {
+            let (title, authors, genres) = item.export();
+            let mut entry = format!("- **{}**", escape(title));
+            if !authors.is_empty() {
+                entry.push_str(&format!(" by {}", join(authors)));
+            }
+            if !genres.is_empty() {
+                entry.push_str(&format!(" ({})", join(genres)));
+            }
+            if shelf.is_favorite(module, code) {
+                entry.push_str(" ★");
+            }
+            entry.push_str(&format!(
+                " {}\n",
+                code_span(&format!("{} {}", module, code))
+            ));
+            entry
+        }
+        None => String::new(),
+    }
+}
+
+fn join(names: &BTreeSet<String>) -> String {
+    names
+        .iter()
+        .map(|n| escape(n))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Put text in a code span, delimited by more backticks than it contains in a
+/// row and padded if it starts or ends with one
+fn code_span(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest + 1);
+    if text.starts_with('`') || text.ends_with('`') {
+        format!("{} {} {}", fence, text, fence)
+    } else {
+        format!("{}{}{}", fence, text, fence)
+    }
+}
+
+/// Escape characters with a meaning in Markdown
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>#|".contains(c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelf::test_util::set;
+
+    #[test]
+    fn groups_items() {
+        let mut shelf = Shelf::new();
+        shelf.add_item(
+            "mod",
+            "1",
+            String::from("*One*"),
+            set(&["A", "B"]),
+            set(&[]),
+        );
+        shelf.add_item("mod", "2", String::from("Two"), set(&[]), set(&["x"]));
+        shelf.edit_item(Some("mod"), Some("1"), None, None, None, true);
+        assert_eq!(
+            "# Reading list\n\
+             \n## A\n\n- **\\*One\\*** by A, B ★ `mod 1`\n\
+             \n## B\n\n- **\\*One\\*** by A, B ★ `mod 1`\n\
+             \n## Unknown author\n\n- **Two** (x) `mod 2`\n",
+            export_markdown(&shelf, &GroupBy::Author)
+        );
+    }
+
+    #[test]
+    fn code_spans_fit_backticks() {
+        assert_eq!("`mod 1`", code_span("mod 1"));
+        assert_eq!("``a`b``", code_span("a`b"));
+        assert_eq!("``` ``a ```", code_span("``a"));
+    }
+}
@@ -1,21 +1,31 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::Command,
     string::FromUtf8Error,
 };
 
+use crate::local_file::LOCAL_MODULE;
+
 #[derive(Debug)]
 pub enum ModuleError {
     FromUtf8Error(FromUtf8Error),
     IoError(std::io::Error),
     NoValidModule,
     MalformedMetadata,
+    DownloadFailed(String),
+    UnsupportedFile,
+    ZipError(zip::result::ZipError),
+    XmlError(roxmltree::Error),
+    PdfError(lopdf::Error),
+    /// Items of the built-in local module have no URL or metadata to fetch,
+    /// and can't be downloaded again
+    LocalItem,
 }
 
 /// A module is defined by a name and the path to the module file.
 /// The media_type parameter is set when loading modules to speed things up.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct Module {
     mod_file: PathBuf,
     media_type: String,
@@ -31,7 +41,7 @@ impl Module {
 
     /// Check if a URL can be handled by the module
     fn is_url_valid(&self, url: &str) -> Result<bool, ModuleError> {
-        match Command::new(&self.mod_file).args(&["check", url]).output() {
+        match Command::new(&self.mod_file).args(["check", url]).output() {
             Ok(out) => match String::from_utf8(out.stdout) {
                 Ok(out) => {
                     if strip(out) == "1" {
@@ -48,9 +58,9 @@ impl Module {
 
     /// Given a URL, return the respective item code
     fn derive_code(&self, url: &str) -> Result<String, ModuleError> {
-        match Command::new(&self.mod_file).args(&["code", url]).output() {
+        match Command::new(&self.mod_file).args(["code", url]).output() {
             Ok(out) => match String::from_utf8(out.stdout) {
-                Ok(out) => Ok(out),
+                Ok(out) => Ok(strip(out)),
                 Err(e) => Err(ModuleError::FromUtf8Error(e)),
             },
             Err(e) => Err(ModuleError::IoError(e)),
@@ -59,9 +69,9 @@ impl Module {
 
     /// Given an item code, return the respective URL
     fn derive_url(&self, code: &str) -> Result<String, ModuleError> {
-        match Command::new(&self.mod_file).args(&["url", code]).output() {
+        match Command::new(&self.mod_file).args(["url", code]).output() {
             Ok(out) => match String::from_utf8(out.stdout) {
-                Ok(out) => Ok(out),
+                Ok(out) => Ok(strip(out)),
                 Err(e) => Err(ModuleError::FromUtf8Error(e)),
             },
             Err(e) => Err(ModuleError::IoError(e)),
@@ -71,7 +81,7 @@ impl Module {
     /// Get the title, authors, and genres of a book
     fn get_metadata(&self, code: &str) -> Result<(String, String, String), ModuleError> {
         match Command::new(&self.mod_file)
-            .args(&["metadata", code])
+            .args(["metadata", code])
             .output()
         {
             Ok(out) => match String::from_utf8(out.stdout) {
@@ -81,11 +91,7 @@ impl Module {
                     if let Some(title) = lines.next() {
                         if let Some(authors) = lines.next() {
                             if let Some(genres) = lines.next() {
-                                return Ok((
-                                    title.to_string(),
-                                    authors.to_string(),
-                                    genres.to_string(),
-                                ));
+                                Ok((title.to_string(), authors.to_string(), genres.to_string()))
                             } else {
                                 Err(ModuleError::MalformedMetadata)
                             }
@@ -102,29 +108,41 @@ impl Module {
         }
     }
 
-    /// Download a book given its code. Everything here is handled by the module.
-    fn download(&self, code: &str, dest_dir: &str) {
+    /// Download a book given its code, unless its directory has files
+    /// already. Everything here is handled by the module, and the directory is
+    /// removed again if it fails so the item isn't taken as downloaded.
+    /// Returns what the module printed.
+    fn download(&self, code: &str, dest_dir: &str) -> Result<String, ModuleError> {
         let pb = PathBuf::from(&dest_dir);
-        if !&pb.exists() {
-            match std::fs::create_dir_all(pb) {
-                Ok(()) => {
-                    match Command::new(&self.mod_file).args(&["download", code, dest_dir]).output(){
-                        Ok(output) => {
-                            let out = String::from_utf8(output.stdout).expect("Error converting UTF8 output");
-                            println!("{}", out);
-                        }
-                        Err(e) => println!("Error downloading item: {}", e)
-                    }
-                }
-                Err(e) => {
-                    println!("Error creating item data directory: {}", e);
+        if has_files(&pb) {
+            return Ok(String::new());
+        }
+        if let Err(e) = std::fs::create_dir_all(&pb) {
+            return Err(ModuleError::IoError(e));
+        }
+        let result = match Command::new(&self.mod_file)
+            .args(["download", code, dest_dir])
+            .output()
+        {
+            Ok(output) => {
+                if output.status.success() {
+                    Ok(strip(String::from_utf8_lossy(&output.stdout).into_owned()))
+                } else {
+                    let err = String::from_utf8_lossy(&output.stderr);
+                    Err(ModuleError::DownloadFailed(strip(err.into_owned())))
                 }
             }
+            Err(e) => Err(ModuleError::IoError(e)),
+        };
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&pb);
         }
+        result
     }
 }
 
 /// Handles everything about modules.
+#[derive(Clone)]
 pub struct ModuleHandler {
     modules: BTreeMap<String, Module>,
 }
@@ -133,7 +151,7 @@ impl ModuleHandler {
     /// Load  available modules from modules_path
     pub fn new(modules_dir: &PathBuf) -> ModuleHandler {
         let mut modules: BTreeMap<String, Module> = BTreeMap::new();
-        match std::fs::read_dir(&modules_dir) {
+        match std::fs::read_dir(modules_dir) {
             Ok(files) => {
                 // Look at all files in `modules_dir/`
                 for file in files {
@@ -141,15 +159,11 @@ impl ModuleHandler {
                         Ok(file) => {
                             let pb: PathBuf = file.path();
                             // if execution of `module media` is successful
-                            match Command::new(&pb).args(&["media"]).output() {
+                            match Command::new(&pb).args(["media"]).output() {
                                 Ok(out) => {
                                     // Get media type
-                                    let media: Option<String> = {
-                                        match String::from_utf8(out.stdout) {
-                                            Ok(media) => Some(media),
-                                            Err(_e) => None,
-                                        }
-                                    };
+                                    let media: Option<String> =
+                                        { String::from_utf8(out.stdout).ok().map(strip) };
                                     // Get module name ( = fie name)
                                     let name: Option<&str> =
                                         pb.as_path().file_name().and_then(std::ffi::OsStr::to_str);
@@ -197,6 +211,9 @@ impl ModuleHandler {
 
     /// Given a module and code, derive the corresponding URL
     pub fn derive_url(&self, module: &str, code: &str) -> Result<String, ModuleError> {
+        if module == LOCAL_MODULE {
+            return Err(ModuleError::LocalItem);
+        }
         match self.modules.get(module) {
             Some(module) => module.derive_url(code),
             None => Err(ModuleError::NoValidModule),
@@ -231,29 +248,52 @@ impl ModuleHandler {
         module: &str,
         code: &str,
     ) -> Result<(String, String, String), ModuleError> {
+        if module == LOCAL_MODULE {
+            return Err(ModuleError::LocalItem);
+        }
         match self.modules.get(module) {
             Some(module) => module.get_metadata(code),
             None => Err(ModuleError::NoValidModule),
         }
     }
 
-    /// Given a module and code, download item to the provided directory
-    pub fn download(&self, module: &str, code: &str, dest_dir: &PathBuf) -> Result<(), ModuleError> {
-        let dest_dir = &*dest_dir.clone().into_os_string().into_string().unwrap();
+    /// Given a module and code, download item to the provided directory,
+    /// returning what the module printed. Local items were copied there when
+    /// added, so they only need to be present.
+    pub fn download(
+        &self,
+        module: &str,
+        code: &str,
+        dest_dir: &Path,
+    ) -> Result<String, ModuleError> {
+        if module == LOCAL_MODULE {
+            return if has_files(dest_dir) {
+                Ok(String::new())
+            } else {
+                Err(ModuleError::LocalItem)
+            };
+        }
+        let dest_dir = &*dest_dir
+            .to_path_buf()
+            .into_os_string()
+            .into_string()
+            .unwrap();
         match self.modules.get(module) {
-            Some(m) => {
-                m.download(code, dest_dir);
-                Ok(())
-            }
+            Some(m) => m.download(code, dest_dir),
             None => Err(ModuleError::NoValidModule),
         }
     }
 }
 
+/// Check if a directory exists and has anything in it
+fn has_files(dir: &Path) -> bool {
+    std::fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some())
+}
+
 /// Strip tailing newlines and spaces from String
 fn strip(mut string: String) -> String {
     while string.ends_with("\n") || string.ends_with(" ") {
         string.pop();
     }
-    return string;
+    string
 }
@@ -0,0 +1,425 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fsio::{list_files, mime_type};
+use crate::html::{escape, file_url, slug, unslug};
+use crate::shelf::*;
+
+const NAVIGATION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=navigation";
+const ACQUISITION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+
+/// The feeds making up the catalog. All feeds live in the same directory so
+/// links between them are the same whether written as files or served.
+#[derive(Debug, PartialEq)]
+pub enum Feed {
+    Root,
+    Authors,
+    Genres,
+    Modules,
+    Favorites,
+    All,
+    Author(String),
+    Genre(String),
+    Module(String),
+}
+
+impl Feed {
+    /// Get the file name of the feed
+    pub fn path(&self) -> String {
+        match self {
+            Feed::Root => String::from("root.xml"),
+            Feed::Authors => String::from("authors.xml"),
+            Feed::Genres => String::from("genres.xml"),
+            Feed::Modules => String::from("modules.xml"),
+            Feed::Favorites => String::from("favorites.xml"),
+            Feed::All => String::from("all.xml"),
+            Feed::Author(name) => format!("author-{}.xml", slug(name)),
+            Feed::Genre(name) => format!("genre-{}.xml", slug(name)),
+            Feed::Module(name) => format!("module-{}.xml", slug(name)),
+        }
+    }
+
+    /// Get the feed corresponding to a file name
+    pub fn from_path(path: &str) -> Option<Feed> {
+        let name = path.strip_suffix(".xml")?;
+        match name {
+            "root" => Some(Feed::Root),
+            "authors" => Some(Feed::Authors),
+            "genres" => Some(Feed::Genres),
+            "modules" => Some(Feed::Modules),
+            "favorites" => Some(Feed::Favorites),
+            "all" => Some(Feed::All),
+            _ => {
+                if let Some(s) = name.strip_prefix("author-") {
+                    unslug(s).map(Feed::Author)
+                } else if let Some(s) = name.strip_prefix("genre-") {
+                    unslug(s).map(Feed::Genre)
+                } else if let Some(s) = name.strip_prefix("module-") {
+                    unslug(s).map(Feed::Module)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn title(&self) -> String {
+        match self {
+            Feed::Root => String::from("Bookshelf"),
+            Feed::Authors => String::from("By author"),
+            Feed::Genres => String::from("By genre"),
+            Feed::Modules => String::from("By module"),
+            Feed::Favorites => String::from("Favorites"),
+            Feed::All => String::from("All items"),
+            Feed::Author(name) | Feed::Genre(name) | Feed::Module(name) => name.clone(),
+        }
+    }
+}
+
+/// Where acquisition links of a catalog point to
+pub enum FileLinks {
+    /// `file://` URLs to the files in data_dir
+    Local,
+    /// Paths under `/files/` on the built-in server
+    Server,
+}
+
+/// Render an OPDS feed from the shelf. Returns None for feeds of authors,
+/// genres or modules not in the shelf.
+pub fn render_feed(
+    shelf: &Shelf,
+    data_dir: &Path,
+    feed: &Feed,
+    links: &FileLinks,
+) -> Option<String> {
+    let index = shelf.get_index();
+    let mut entries = String::new();
+    let kind = match feed {
+        Feed::Root => {
+            for sub in [
+                Feed::All,
+                Feed::Authors,
+                Feed::Genres,
+                Feed::Modules,
+                Feed::Favorites,
+            ]
+            .iter()
+            {
+                let kind = if *sub == Feed::All || *sub == Feed::Favorites {
+                    ACQUISITION_TYPE
+                } else {
+                    NAVIGATION_TYPE
+                };
+                entries.push_str(&navigation_entry(&sub.title(), &sub.path(), kind));
+            }
+            NAVIGATION_TYPE
+        }
+        Feed::Authors | Feed::Genres | Feed::Modules => {
+            let mut groups: BTreeMap<&String, usize> = BTreeMap::new();
+            for ((module, _), item) in index.iter() {
+                let (_, authors, genres) = item.export();
+                let names: Vec<&String> = match feed {
+                    Feed::Authors => authors.iter().collect(),
+                    Feed::Genres => genres.iter().collect(),
+                    _ => vec![module],
+                };
+                for name in names {
+                    *groups.entry(name).or_default() += 1;
+                }
+            }
+            for (name, count) in groups {
+                let sub = match feed {
+                    Feed::Authors => Feed::Author(name.clone()),
+                    Feed::Genres => Feed::Genre(name.clone()),
+                    _ => Feed::Module(name.clone()),
+                };
+                let title = format!("{} ({})", name, count);
+                entries.push_str(&navigation_entry(&title, &sub.path(), ACQUISITION_TYPE));
+            }
+            NAVIGATION_TYPE
+        }
+        _ => {
+            let keys: BTreeSet<&(String, String)> = index
+                .iter()
+                .filter(|((module, code), item)| {
+                    let (_, authors, genres) = item.export();
+                    match feed {
+                        Feed::Favorites => shelf.is_favorite(module, code),
+                        Feed::Author(name) => authors.contains(name),
+                        Feed::Genre(name) => genres.contains(name),
+                        Feed::Module(name) => module == name,
+                        _ => true,
+                    }
+                })
+                .map(|(key, _)| key)
+                .collect();
+            if keys.is_empty() && *feed != Feed::Favorites && *feed != Feed::All {
+                return None;
+            }
+            for (module, code) in keys {
+                entries.push_str(&acquisition_entry(shelf, data_dir, module, code, links));
+            }
+            ACQUISITION_TYPE
+        }
+    };
+    Some(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:opds=\"http://opds-spec.org/2010/catalog\">\n\
+         <id>urn:bookshelf:{}</id>\n\
+         <title>{}</title>\n\
+         <updated>{}</updated>\n\
+         <link rel=\"self\" href=\"{}\" type=\"{}\"/>\n\
+         <link rel=\"start\" href=\"{}\" type=\"{}\"/>\n\
+         {}</feed>\n",
+        escape(&feed.path()),
+        escape(&feed.title()),
+        now(),
+        escape(&feed.path()),
+        kind,
+        Feed::Root.path(),
+        NAVIGATION_TYPE,
+        entries
+    ))
+}
+
+/// Write every feed of the catalog to out_dir, with acquisition links pointing
+/// to local files
+pub fn export_opds(shelf: &Shelf, data_dir: &Path, out_dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(out_dir)?;
+    let mut feeds: Vec<Feed> = vec![
+        Feed::Root,
+        Feed::Authors,
+        Feed::Genres,
+        Feed::Modules,
+        Feed::Favorites,
+        Feed::All,
+    ];
+    let mut authors: BTreeSet<&String> = BTreeSet::new();
+    let mut genres: BTreeSet<&String> = BTreeSet::new();
+    let mut modules: BTreeSet<&String> = BTreeSet::new();
+    for ((module, _), item) in shelf.get_index().iter() {
+        let (_, i_authors, i_genres) = item.export();
+        authors.extend(i_authors);
+        genres.extend(i_genres);
+        modules.insert(module);
+    }
+    feeds.extend(authors.into_iter().map(|a| Feed::Author(a.clone())));
+    feeds.extend(genres.into_iter().map(|g| Feed::Genre(g.clone())));
+    feeds.extend(modules.into_iter().map(|m| Feed::Module(m.clone())));
+    for feed in feeds {
+        if let Some(data) = render_feed(shelf, data_dir, &feed, &FileLinks::Local) {
+            std::fs::write(out_dir.join(feed.path()), data)?;
+        }
+    }
+    Ok(())
+}
+
+fn navigation_entry(title: &str, href: &str, kind: &str) -> String {
+    format!(
+        "<entry>\n<title>{}</title>\n<id>urn:bookshelf:{}</id>\n<updated>{}</updated>\n<link rel=\"subsection\" href=\"{}\" type=\"{}\"/>\n</entry>\n",
+        escape(title),
+        escape(href),
+        now(),
+        escape(href),
+        kind
+    )
+}
+
+fn acquisition_entry(
+    shelf: &Shelf,
+    data_dir: &Path,
+    module: &str,
+    code: &str,
+    links: &FileLinks,
+) -> String {
+    let mut entry = String::from("<entry>\n");
+    if let Some(item) = shelf.get_item(module, code) {
+        let (title, authors, genres) = item.export();
+        entry.push_str(&format!("<title>{}</title>\n", escape(title)));
+        entry.push_str(&format!(
+            "<id>urn:bookshelf:{}:{}</id>\n<updated>{}</updated>\n",
+            escape(module),
+            escape(code),
+            now()
+        ));
+        for author in authors {
+            entry.push_str(&format!(
+                "<author><name>{}</name></author>\n",
+                escape(author)
+            ));
+        }
+        for genre in genres {
+            entry.push_str(&format!(
+                "<category term=\"{}\" label=\"{}\"/>\n",
+                escape(genre),
+                escape(genre)
+            ));
+        }
+    }
+    let mut item_dir: PathBuf = data_dir.to_path_buf();
+    item_dir.push(module);
+    item_dir.push(code);
+    let files = list_files(&item_dir);
+    // Use the first image as cover
+    if let Some(cover) = files.iter().find(|f| mime_type(f).starts_with("image/")) {
+        for rel in [
+            "http://opds-spec.org/image",
+            "http://opds-spec.org/image/thumbnail",
+        ]
+        .iter()
+        {
+            entry.push_str(&format!(
+                "<link rel=\"{}\" href=\"{}\" type=\"{}\"/>\n",
+                rel,
+                escape(&file_link(cover, data_dir, links)),
+                mime_type(cover)
+            ));
+        }
+    }
+    for file in files.iter() {
+        entry.push_str(&format!(
+            "<link rel=\"http://opds-spec.org/acquisition\" href=\"{}\" type=\"{}\"/>\n",
+            escape(&file_link(file, data_dir, links)),
+            mime_type(file)
+        ));
+    }
+    entry.push_str("</entry>\n");
+    entry
+}
+
+/// Get the link to a file in data_dir
+fn file_link(file: &Path, data_dir: &Path, links: &FileLinks) -> String {
+    match links {
+        FileLinks::Local => file_url(&file.canonicalize().unwrap_or_else(|_| file.to_path_buf())),
+        FileLinks::Server => {
+            let rel = file.strip_prefix(data_dir).unwrap_or(file);
+            let mut url = String::from("/files");
+            for component in rel.iter() {
+                url.push('/');
+                for b in component.to_string_lossy().bytes() {
+                    if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+                        url.push(b as char);
+                    } else {
+                        url.push_str(&format!("%{:02X}", b));
+                    }
+                }
+            }
+            url
+        }
+    }
+}
+
+/// Get the current time as an RFC 3339 UTC timestamp
+fn now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    timestamp(secs)
+}
+
+/// Format seconds since the Unix epoch as an RFC 3339 UTC timestamp
+fn timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Convert days since epoch to a civil date
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelf::test_util::{set, shelf};
+
+    /// Render a feed and get the titles of its entries, checking it's valid XML
+    fn titles(shelf: &Shelf, feed: &Feed) -> Option<Vec<String>> {
+        let data = render_feed(shelf, Path::new("/nonexistent"), feed, &FileLinks::Server)?;
+        let doc = roxmltree::Document::parse(&data).unwrap();
+        Some(
+            doc.root_element()
+                .children()
+                .filter(|n| n.has_tag_name("entry"))
+                .filter_map(|n| n.children().find(|c| c.has_tag_name("title")))
+                .filter_map(|t| t.text().map(str::to_string))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn feed_paths_round_trip() {
+        for feed in [
+            Feed::Root,
+            Feed::Favorites,
+            Feed::Author(String::from("A B")),
+            Feed::Genre(String::from("Sci-Fi/Fantasy")),
+            Feed::Module(String::from("mod")),
+        ]
+        .iter()
+        {
+            assert_eq!(Some(feed), Feed::from_path(&feed.path()).as_ref());
+        }
+        assert_eq!(None, Feed::from_path("root"));
+        assert_eq!(None, Feed::from_path("other.xml"));
+    }
+
+    #[test]
+    fn renders_feeds() {
+        let mut shelf = shelf();
+        shelf.add_item(
+            "b",
+            "4",
+            String::from("War & Peace"),
+            set(&["Tolstoy"]),
+            set(&["classic"]),
+        );
+        assert_eq!(5, titles(&shelf, &Feed::Root).unwrap().len());
+        assert_eq!(
+            vec!["Austen (1)", "Herbert (2)", "Tolstoy (1)"],
+            titles(&shelf, &Feed::Authors).unwrap()
+        );
+        assert_eq!(
+            vec!["Dune", "Dune Messiah"],
+            titles(&shelf, &Feed::Genre(String::from("sf"))).unwrap()
+        );
+        assert_eq!(
+            vec!["War & Peace"],
+            titles(&shelf, &Feed::Genre(String::from("classic"))).unwrap()
+        );
+        assert_eq!(vec!["Emma"], titles(&shelf, &Feed::Favorites).unwrap());
+        assert_eq!(None, titles(&shelf, &Feed::Author(String::from("D"))));
+    }
+
+    #[test]
+    fn server_links_are_encoded() {
+        let link = file_link(
+            Path::new("/data/mod/1/a b.epub"),
+            Path::new("/data"),
+            &FileLinks::Server,
+        );
+        assert_eq!("/files/mod/1/a%20b.epub", link);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!("1970-01-01T00:00:00Z", timestamp(0));
+        assert_eq!("2000-02-29T01:02:03Z", timestamp(951_782_400 + 3723));
+    }
+}
@@ -0,0 +1,500 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::config::*;
+use crate::fsio::{mime_type, write_shelf};
+use crate::items::{add_by_code, get_item_dir};
+use crate::module_handler::*;
+use crate::opds::{render_feed, Feed, FileLinks};
+use crate::shelf::*;
+
+/// Body of requests editing an item. Authors and genres are comma-separated
+/// like on the command line.
+#[derive(Deserialize)]
+struct EditRequest {
+    title: Option<String>,
+    authors: Option<String>,
+    genres: Option<String>,
+}
+
+/// Body of requests adding an item, either by URL or by module and code
+#[derive(Deserialize)]
+struct AddRequest {
+    url: Option<String>,
+    module: Option<String>,
+    code: Option<String>,
+    #[serde(default)]
+    download: bool,
+}
+
+/// Items being downloaded in the background
+type Downloads = Arc<Mutex<BTreeSet<(String, String)>>>;
+
+/// Answer of the JSON API to a request
+enum Reply {
+    /// Status code and body
+    Json(u16, Value),
+    /// Add an item once a worker thread has fetched its metadata
+    Fetch(AddRequest),
+}
+
+impl From<(u16, Value)> for Reply {
+    fn from((status, body): (u16, Value)) -> Reply {
+        Reply::Json(status, body)
+    }
+}
+
+/// Item fetched by a worker thread for a request adding it, which waits for
+/// its answer until the item is added to the shelf
+struct Fetched {
+    request: Request,
+    download: bool,
+    /// Module and code of the item, and a shelf with only the item
+    result: Result<((String, String), Shelf), ModuleError>,
+}
+
+/// How long to wait for requests before adding the items fetched meanwhile
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Serve the OPDS catalog under `/opds/`, the JSON API under `/api/` and
+/// downloaded files under `/files/` until the process is killed. The index is
+/// saved after every change made through the API. Module commands adding and
+/// downloading items run in threads of their own so they don't hold up other
+/// requests, and the shelf is only changed by this thread.
+pub fn serve(
+    config: &Config,
+    shelf: &mut Shelf,
+    module_handler: &ModuleHandler,
+    bind: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let server = Server::http(bind)?;
+    println!("Serving OPDS catalog at http://{}/opds/root.xml", bind);
+    println!("Serving API at http://{}/api/items", bind);
+    let downloads: Downloads = Arc::default();
+    let (tx, rx) = mpsc::channel::<Fetched>();
+    loop {
+        for fetched in rx.try_iter() {
+            let url = fetched.request.url().to_string();
+            let (status, body) = add_fetched(
+                config,
+                shelf,
+                module_handler,
+                &downloads,
+                fetched.download,
+                fetched.result,
+            );
+            if let Err(e) = respond_json(fetched.request, status, &body) {
+                println!("Error responding to {}: {}", url, e);
+            }
+        }
+        let mut request = match server.recv_timeout(POLL_INTERVAL)? {
+            Some(request) => request,
+            None => continue,
+        };
+        let url = request.url().to_string();
+        let path = url.split('?').next().unwrap_or("");
+        let result = if path == "/api" || path.starts_with("/api/") {
+            match api(&mut request, config, shelf, module_handler, &downloads) {
+                Reply::Json(status, body) => respond_json(request, status, &body),
+                Reply::Fetch(body) => {
+                    fetch_item(request, body, module_handler, &tx);
+                    Ok(())
+                }
+            }
+        } else if path == "/" || path == "/opds" || path == "/opds/" {
+            respond_feed(request, config, shelf, &Feed::Root)
+        } else if let Some(name) = path.strip_prefix("/opds/") {
+            match Feed::from_path(name) {
+                Some(feed) => respond_feed(request, config, shelf, &feed),
+                None => request.respond(Response::empty(404)),
+            }
+        } else if let Some(rel) = path.strip_prefix("/files/") {
+            respond_file(request, &config.data_dir, rel)
+        } else {
+            request.respond(Response::empty(404))
+        };
+        if let Err(e) = result {
+            println!("Error responding to {}: {}", url, e);
+        }
+    }
+}
+
+/// Handle a request to the JSON API
+fn api(
+    request: &mut Request,
+    config: &Config,
+    shelf: &mut Shelf,
+    module_handler: &ModuleHandler,
+    downloads: &Downloads,
+) -> Reply {
+    let url = request.url().to_string();
+    let mut parts = url.splitn(2, '?');
+    let path = parts.next().unwrap_or("");
+    let query = parse_query(parts.next().unwrap_or(""));
+    let segments: Vec<String> = match path
+        .trim_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect::<Option<Vec<String>>>()
+    {
+        Some(segments) => segments,
+        None => return error(400, "Malformed URL").into(),
+    };
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let method = request.method().clone();
+    let (status, body) = match (method, &segments[..]) {
+        (Method::Get, ["api", "items"]) => {
+            let q = |key: &str| query.get(key).map(String::as_str);
+            match shelf.search_item(
+                q("module"),
+                q("title"),
+                q("authors"),
+                q("genres"),
+                q("blacklist"),
+                query.contains_key("broad_search"),
+                query.contains_key("favorite"),
+            ) {
+                Ok(result) => {
+                    let items: Vec<Value> = result
+                        .iter()
+                        .filter_map(|(m, c)| item_json(shelf, m, c))
+                        .collect();
+                    (200, Value::Array(items))
+                }
+                Err(e) => error(400, &format!("Invalid title regex: {}", e)),
+            }
+        }
+        (Method::Post, ["api", "items"]) => {
+            let body: AddRequest = match read_json(request) {
+                Ok(body) => body,
+                Err(e) => return e.into(),
+            };
+            if body.url.is_none() && (body.module.is_none() || body.code.is_none()) {
+                return error(400, "Either url or module and code are required").into();
+            }
+            // items added by URL are only known once the module derives their code
+            if let (None, Some(module), Some(code)) = (&body.url, &body.module, &body.code) {
+                if shelf.has_item(module, code) {
+                    return exists(module, code).into();
+                }
+            }
+            return Reply::Fetch(body);
+        }
+        (method, ["api", "items", module, code]) => {
+            if !shelf.has_item(module, code) {
+                return error(404, "Item not found").into();
+            }
+            match method {
+                Method::Get => (200, item_json(shelf, module, code).unwrap_or(Value::Null)),
+                Method::Patch | Method::Put => {
+                    let body: EditRequest = match read_json(request) {
+                        Ok(body) => body,
+                        Err(e) => return e.into(),
+                    };
+                    shelf.edit_item(
+                        Some(module),
+                        Some(code),
+                        body.title.as_deref(),
+                        body.authors.as_deref(),
+                        body.genres.as_deref(),
+                        false,
+                    );
+                    if let Err(e) = save(config, shelf) {
+                        return e.into();
+                    }
+                    (200, item_json(shelf, module, code).unwrap_or(Value::Null))
+                }
+                Method::Delete => {
+                    shelf.remove_item(module, code);
+                    if let Err(e) = save(config, shelf) {
+                        return e.into();
+                    }
+                    // Only delete downloaded files if asked to
+                    if query.contains_key("files") {
+                        let item_dir = get_item_dir(&config.data_dir, module, code);
+                        if item_dir.exists() {
+                            if let Err(e) = std::fs::remove_dir_all(&item_dir) {
+                                return error(500, &format!("Error deleting files: {}", e)).into();
+                            }
+                        }
+                    }
+                    (200, json!({ "status": "deleted" }))
+                }
+                _ => error(405, "Method not allowed"),
+            }
+        }
+        (Method::Post, ["api", "items", module, code, "favorite"]) => {
+            if !shelf.has_item(module, code) {
+                return error(404, "Item not found").into();
+            }
+            shelf.edit_item(Some(module), Some(code), None, None, None, true);
+            if let Err(e) = save(config, shelf) {
+                return e.into();
+            }
+            (200, item_json(shelf, module, code).unwrap_or(Value::Null))
+        }
+        (Method::Post, ["api", "items", module, code, "download"]) => {
+            if !shelf.has_item(module, code) {
+                return error(404, "Item not found").into();
+            }
+            if start_download(config, module_handler, downloads, module, code) {
+                (202, json!({ "status": "downloading" }))
+            } else {
+                error(409, "Item is already being downloaded")
+            }
+        }
+        _ => error(404, "Not found"),
+    };
+    Reply::Json(status, body)
+}
+
+/// Fetch the metadata of an item to add in a worker thread, which sends it
+/// back with the request to answer once the item is added
+fn fetch_item(
+    request: Request,
+    body: AddRequest,
+    module_handler: &ModuleHandler,
+    tx: &mpsc::Sender<Fetched>,
+) {
+    let module_handler = module_handler.clone();
+    let tx = tx.clone();
+    thread::spawn(move || {
+        let key = match (body.url, body.module, body.code) {
+            (Some(url), _, _) => module_handler.derive_module(&url).and_then(|module| {
+                let code = module_handler.derive_code(module, &url)?;
+                Ok((module.clone(), code))
+            }),
+            (None, Some(module), Some(code)) => Ok((module, code)),
+            _ => Err(ModuleError::NoValidModule),
+        };
+        let result = key.and_then(|(module, code)| {
+            let mut new_shelf = Shelf::new();
+            add_by_code(&mut new_shelf, &module_handler, &module, &code, None, false)?;
+            Ok(((module, code), new_shelf))
+        });
+        // the server only stops with the process, so it's still receiving
+        let _ = tx.send(Fetched {
+            request,
+            download: body.download,
+            result,
+        });
+    });
+}
+
+/// Add an item fetched by a worker thread, then save the shelf and start
+/// downloading the item if asked to. Returns the answer to the request adding
+/// it.
+fn add_fetched(
+    config: &Config,
+    shelf: &mut Shelf,
+    module_handler: &ModuleHandler,
+    downloads: &Downloads,
+    download: bool,
+    result: Result<((String, String), Shelf), ModuleError>,
+) -> (u16, Value) {
+    let ((module, code), new_shelf) = match result {
+        Ok(fetched) => fetched,
+        Err(e) => return error(422, &format!("{:?}", e)),
+    };
+    // added by URL, or by another request while fetching
+    if shelf.has_item(&module, &code) {
+        return exists(&module, &code);
+    }
+    // The item is only added once its metadata is fetched, and is downloaded
+    // afterwards so a failed download doesn't undo it
+    shelf.import(&new_shelf);
+    if let Err(e) = save(config, shelf) {
+        return e;
+    }
+    if download {
+        start_download(config, module_handler, downloads, &module, &code);
+        (
+            202,
+            json!({ "status": "downloading", "module": module, "code": code }),
+        )
+    } else {
+        (
+            201,
+            json!({ "status": "added", "module": module, "code": code }),
+        )
+    }
+}
+
+/// Answer to requests adding an item which is on the shelf already
+fn exists(module: &str, code: &str) -> (u16, Value) {
+    (
+        200,
+        json!({ "status": "exists", "module": module, "code": code }),
+    )
+}
+
+/// Get the JSON representation of an item
+fn item_json(shelf: &Shelf, module: &str, code: &str) -> Option<Value> {
+    let item = shelf.get_item(module, code)?;
+    let (title, authors, genres) = item.export();
+    Some(json!({
+        "module": module,
+        "code": code,
+        "title": title,
+        "authors": authors,
+        "genres": genres,
+        "favorite": shelf.is_favorite(module, code),
+    }))
+}
+
+/// Save the shelf after a change
+fn save(config: &Config, shelf: &Shelf) -> Result<(), (u16, Value)> {
+    match write_shelf(shelf, &config.index_file) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(error(500, &format!("Error writing index file: {:?}", e))),
+    }
+}
+
+/// Download an item in a thread of its own, printing errors as nobody waits
+/// for the result. Returns false if the item is being downloaded already.
+fn start_download(
+    config: &Config,
+    module_handler: &ModuleHandler,
+    downloads: &Downloads,
+    module: &str,
+    code: &str,
+) -> bool {
+    let key = (module.to_string(), code.to_string());
+    if !downloads.lock().unwrap().insert(key.clone()) {
+        return false;
+    }
+    let dest_dir = get_item_dir(&config.data_dir, module, code);
+    let module_handler = module_handler.clone();
+    let downloads = downloads.clone();
+    thread::spawn(move || {
+        let (module, code) = &key;
+        if let Err(e) = module_handler.download(module, code, &dest_dir) {
+            println!("Error downloading {} {}: {:?}", module, code, e);
+        }
+        downloads.lock().unwrap().remove(&key);
+    });
+    true
+}
+
+fn respond_json(request: Request, status: u16, body: &Value) -> std::io::Result<()> {
+    request.respond(
+        Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(content_type("application/json")),
+    )
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (status, json!({ "error": message }))
+}
+
+/// Parse the JSON body of a request
+fn read_json<T: serde::de::DeserializeOwned>(request: &mut Request) -> Result<T, (u16, Value)> {
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        return Err(error(400, &format!("Error reading body: {}", e)));
+    }
+    match serde_json::from_str(&body) {
+        Ok(data) => Ok(data),
+        Err(e) => Err(error(400, &format!("Invalid JSON body: {}", e))),
+    }
+}
+
+/// Parse a query string into its keys and values. Keys without values map to an
+/// empty string.
+fn parse_query(query: &str) -> BTreeMap<String, String> {
+    let mut result: BTreeMap<String, String> = BTreeMap::new();
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let mut kv = pair.splitn(2, '=');
+        let key = kv.next().unwrap_or("").replace('+', " ");
+        let value = kv.next().unwrap_or("").replace('+', " ");
+        if let (Some(key), Some(value)) = (percent_decode(&key), percent_decode(&value)) {
+            result.insert(key, value);
+        }
+    }
+    result
+}
+
+fn respond_feed(
+    request: Request,
+    config: &Config,
+    shelf: &Shelf,
+    feed: &Feed,
+) -> std::io::Result<()> {
+    match render_feed(shelf, &config.data_dir, feed, &FileLinks::Server) {
+        Some(data) => request
+            .respond(Response::from_string(data).with_header(content_type("application/atom+xml"))),
+        None => request.respond(Response::empty(404)),
+    }
+}
+
+/// Stream a file from data_dir, refusing paths escaping it
+fn respond_file(request: Request, data_dir: &Path, rel: &str) -> std::io::Result<()> {
+    let rel = match percent_decode(rel) {
+        Some(rel) => PathBuf::from(rel),
+        None => return request.respond(Response::empty(400)),
+    };
+    if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+        return request.respond(Response::empty(403));
+    }
+    let file = data_dir.join(rel);
+    if !file.is_file() {
+        return request.respond(Response::empty(404));
+    }
+    match File::open(&file) {
+        Ok(f) => {
+            request.respond(Response::from_file(f).with_header(content_type(mime_type(&file))))
+        }
+        Err(_e) => request.respond(Response::empty(500)),
+    }
+}
+
+pub fn content_type(mime: &str) -> Header {
+    // Header values built from static MIME types are always valid
+    Header::from_bytes(&b"Content-Type"[..], mime.as_bytes()).unwrap()
+}
+
+/// Decode `%XX` escapes in a URL path
+pub fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(Some(String::from("a b/é")), percent_decode("a%20b/%C3%A9"));
+        assert_eq!(None, percent_decode("%2"));
+        assert_eq!(None, percent_decode("%zz"));
+        assert_eq!(None, percent_decode("%FF"));
+    }
+
+    #[test]
+    fn parses_queries() {
+        let query = parse_query("title=a+b&favorite&authors=C%2CD&");
+        assert_eq!(Some(&String::from("a b")), query.get("title"));
+        assert_eq!(Some(&String::new()), query.get("favorite"));
+        assert_eq!(Some(&String::from("C,D")), query.get("authors"));
+        assert_eq!(3, query.len());
+    }
+}
@@ -21,7 +21,22 @@ impl Item {
 
     /// Get the title, authors, and genres of the item
     pub fn export(&self) -> (&String, &BTreeSet<String>, &BTreeSet<String>) {
-        return (&self.title, &self.authors, &self.genres);
+        (&self.title, &self.authors, &self.genres)
+    }
+}
+
+/// The state of an item in a shelf, used to undo and redo changes. `item` is
+/// None if the item isn't in the shelf.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ItemState {
+    key: (String, String),
+    item: Option<Item>,
+    favorite: bool,
+}
+
+impl ItemState {
+    pub fn key(&self) -> &(String, String) {
+        &self.key
     }
 }
 
@@ -32,6 +47,12 @@ pub struct Shelf {
     favorites: BTreeSet<(String, String)>,
 }
 
+impl Default for Shelf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Shelf {
     pub fn new() -> Shelf {
         Shelf {
@@ -67,6 +88,7 @@ impl Shelf {
     }
 
     /// Search for items matching the provided parameters
+    #[allow(clippy::too_many_arguments)]
     pub fn search_item(
         &self,
         module: Option<&str>,
@@ -99,7 +121,7 @@ impl Shelf {
             for key in result.clone() {
                 for author in authors.split(",") {
                     if let Some(item) = self.index.get(&key) {
-                        if !item.authors.contains(&author.to_string()) {
+                        if !item.authors.contains(author) {
                             result.remove(&key);
                         }
                     } else {
@@ -116,7 +138,7 @@ impl Shelf {
                 for key in result.clone() {
                     for genre in genres.split(",") {
                         if let Some(item) = self.index.get(&key) {
-                            if !item.genres.contains(&genre.to_string()) {
+                            if !item.genres.contains(genre) {
                                 result.remove(&key);
                             }
                         } else {
@@ -130,7 +152,7 @@ impl Shelf {
                     let mut matches: bool = true;
                     for genre in genres.split(",") {
                         if let Some(item) = self.index.get(&key) {
-                            if !item.genres.contains(&genre.to_string()) {
+                            if !item.genres.contains(genre) {
                                 matches = false;
                             }
                         } else {
@@ -149,7 +171,7 @@ impl Shelf {
             for key in result.clone() {
                 for genre in blacklist.split(",") {
                     if let Some(item) = self.index.get(&key) {
-                        if item.genres.contains(&genre.to_string()) {
+                        if item.genres.contains(genre) {
                             result.remove(&key);
                         }
                     } else {
@@ -188,6 +210,12 @@ impl Shelf {
         self.favorites.remove(&key);
     }
 
+    /// Check if an item is in favorites
+    pub fn is_favorite(&self, module: &str, code: &str) -> bool {
+        self.favorites
+            .contains(&(module.to_string(), code.to_string()))
+    }
+
     /// Get the item corresponding to the module and code
     pub fn get_item(&self, module: &str, code: &str) -> Option<&Item> {
         self.index.get(&(module.to_string(), code.to_string()))
@@ -227,11 +255,95 @@ impl Shelf {
             }
         }
         // insert if item wasn't in favorites
+        if favorite && !self.favorites.remove(&k) {
+            self.favorites.insert(k);
+        }
+    }
+
+    /// Add or remove an item from favorites. Returns whether anything changed.
+    pub fn set_favorite(&mut self, module: &str, code: &str, favorite: bool) -> bool {
+        let k = (module.to_string(), code.to_string());
         if favorite {
-            if !self.favorites.remove(&k) {
-                self.favorites.insert(k);
+            self.index.contains_key(&k) && self.favorites.insert(k)
+        } else {
+            self.favorites.remove(&k)
+        }
+    }
+
+    /// Add a genre to an item. Returns whether the item changed.
+    pub fn add_genre(&mut self, module: &str, code: &str, genre: &str) -> bool {
+        match self.index.get_mut(&(module.to_string(), code.to_string())) {
+            Some(item) => item.genres.insert(genre.to_string()),
+            None => false,
+        }
+    }
+
+    /// Remove a genre from an item. Returns whether the item changed.
+    pub fn remove_genre(&mut self, module: &str, code: &str, genre: &str) -> bool {
+        match self.index.get_mut(&(module.to_string(), code.to_string())) {
+            Some(item) => item.genres.remove(genre),
+            None => false,
+        }
+    }
+
+    /// Get the state of items, including items not in the shelf
+    pub fn snapshot(&self, keys: &[(String, String)]) -> Vec<ItemState> {
+        keys.iter()
+            .map(|key| ItemState {
+                key: key.clone(),
+                item: self.index.get(key).cloned(),
+                favorite: self.favorites.contains(key),
+            })
+            .collect()
+    }
+
+    /// Set the state of an item, adding or removing it as needed
+    pub fn restore(&mut self, state: &ItemState) {
+        match &state.item {
+            Some(item) => {
+                self.index.insert(state.key.clone(), item.clone());
+            }
+            None => {
+                self.index.remove(&state.key);
+            }
+        }
+        if state.favorite {
+            self.favorites.insert(state.key.clone());
+        } else {
+            self.favorites.remove(&state.key);
+        }
+    }
+
+    /// Get the keys of items which differ between self and another shelf
+    pub fn changed_keys(&self, other: &Shelf) -> Vec<(String, String)> {
+        let keys: BTreeSet<&(String, String)> = self
+            .index
+            .keys()
+            .chain(other.index.keys())
+            .chain(self.favorites.iter())
+            .chain(other.favorites.iter())
+            .collect();
+        keys.into_iter()
+            .filter(|k| {
+                self.index.get(*k) != other.index.get(*k)
+                    || self.favorites.contains(*k) != other.favorites.contains(*k)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Get a new shelf with only the provided items of self
+    pub fn subset(&self, keys: &BTreeSet<(String, String)>) -> Shelf {
+        let mut result = Shelf::new();
+        for key in keys {
+            if let Some(item) = self.index.get(key) {
+                result.index.insert(key.clone(), item.clone());
+                if self.favorites.contains(key) {
+                    result.favorites.insert(key.clone());
+                }
             }
         }
+        result
     }
 
     /// Import a shelf into self, extending self's index and favorites
@@ -247,3 +359,38 @@ impl Shelf {
         }
     }
 }
+
+/// Fixtures shared by the tests of modules working on shelves
+#[cfg(test)]
+pub mod test_util {
+    use super::Shelf;
+    use std::collections::BTreeSet;
+
+    /// Build a set of authors or genres
+    pub fn set(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    /// Get a shelf of three items from two modules, two of them by the same
+    /// author, with `a 2` as favorite
+    pub fn shelf() -> Shelf {
+        let mut shelf = Shelf::new();
+        shelf.add_item(
+            "a",
+            "1",
+            String::from("Dune"),
+            set(&["Herbert"]),
+            set(&["sf"]),
+        );
+        shelf.add_item("a", "2", String::from("Emma"), set(&["Austen"]), set(&[]));
+        shelf.add_item(
+            "b",
+            "3",
+            String::from("Dune Messiah"),
+            set(&["Herbert"]),
+            set(&["sf"]),
+        );
+        shelf.edit_item(Some("a"), Some("2"), None, None, None, true);
+        shelf
+    }
+}
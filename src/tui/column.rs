@@ -0,0 +1,140 @@
+use tui::layout::Constraint;
+
+/// Columns the index table can show
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Favorite,
+    Title,
+    Authors,
+    Genres,
+    Module,
+    Code,
+    Status,
+    MediaType,
+}
+
+impl Column {
+    /// Get a column from its name in the config
+    pub fn from_name(name: &str) -> Option<Column> {
+        match name {
+            "favorite" => Some(Column::Favorite),
+            "title" => Some(Column::Title),
+            "authors" => Some(Column::Authors),
+            "genres" => Some(Column::Genres),
+            "module" => Some(Column::Module),
+            "code" => Some(Column::Code),
+            "status" => Some(Column::Status),
+            "media_type" => Some(Column::MediaType),
+            _ => None,
+        }
+    }
+
+    pub fn header(&self) -> &'static str {
+        match self {
+            Column::Favorite => "",
+            Column::Title => "Title",
+            Column::Authors => "Authors",
+            Column::Genres => "Genres",
+            Column::Module => "Module",
+            Column::Code => "Code",
+            Column::Status => "Status",
+            Column::MediaType => "Media",
+        }
+    }
+
+    /// Get the width of a column configured without one
+    pub fn default_width(&self) -> Constraint {
+        match self {
+            Column::Favorite => Constraint::Length(1),
+            Column::Title => Constraint::Percentage(30),
+            Column::Authors => Constraint::Percentage(20),
+            Column::Genres => Constraint::Percentage(25),
+            Column::Module | Column::Code => Constraint::Percentage(5),
+            Column::Status => Constraint::Length(13),
+            Column::MediaType => Constraint::Length(5),
+        }
+    }
+}
+
+/// Get the columns shown when none are configured
+pub fn default_columns() -> Vec<(Column, Constraint)> {
+    [
+        Column::Favorite,
+        Column::Title,
+        Column::Authors,
+        Column::Genres,
+        Column::Module,
+        Column::Code,
+        Column::Status,
+    ]
+    .iter()
+    .map(|c| (*c, c.default_width()))
+    .collect()
+}
+
+/// Parse a column width, either a number of cells like `12` or a percentage
+/// of the table like `30%`
+pub fn parse_width(width: &str) -> Option<Constraint> {
+    let width = width.trim();
+    match width.strip_suffix('%') {
+        Some(percentage) => match percentage.trim().parse::<u16>() {
+            Ok(p) if p <= 100 => Some(Constraint::Percentage(p)),
+            _ => None,
+        },
+        None => width.parse::<u16>().ok().map(Constraint::Length),
+    }
+}
+
+/// Get the widths of columns in a table `width` cells wide, with a cell
+/// between columns. Columns are laid out here rather than by the table so the
+/// position of each one is known when clicking headers.
+pub fn column_widths(columns: &[(Column, Constraint)], width: u16) -> Vec<u16> {
+    let spacing = (columns.len() as u16).saturating_sub(1);
+    let available = width.saturating_sub(spacing);
+    let mut left = available;
+    columns
+        .iter()
+        .map(|(_, constraint)| {
+            let width = match *constraint {
+                Constraint::Length(length) => length,
+                Constraint::Percentage(p) => (u32::from(available) * u32::from(p) / 100) as u16,
+                Constraint::Ratio(n, d) => {
+                    (u64::from(available) * u64::from(n) / u64::from(d.max(1))) as u16
+                }
+                Constraint::Min(v) | Constraint::Max(v) => v,
+            }
+            .min(left);
+            left -= width;
+            width
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_config() {
+        assert_eq!(Some(Column::MediaType), Column::from_name("media_type"));
+        assert_eq!(None, Column::from_name("Title"));
+        assert_eq!(Some(Constraint::Length(12)), parse_width(" 12"));
+        assert_eq!(Some(Constraint::Percentage(30)), parse_width("30 %"));
+        assert_eq!(None, parse_width("101%"));
+        assert_eq!(None, parse_width("-1"));
+        assert_eq!(None, parse_width("wide"));
+    }
+
+    #[test]
+    fn lays_out_columns() {
+        let columns = vec![
+            (Column::Favorite, Constraint::Length(1)),
+            (Column::Title, Constraint::Percentage(50)),
+            (Column::Authors, Constraint::Ratio(1, 4)),
+            (Column::Code, Constraint::Length(100)),
+        ];
+        // 3 cells of spacing leaves 40
+        assert_eq!(vec![1, 20, 10, 9], column_widths(&columns, 43));
+        assert_eq!(vec![0, 0, 0, 0], column_widths(&columns, 2));
+    }
+}
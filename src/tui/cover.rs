@@ -0,0 +1,456 @@
+use image::imageops::FilterType;
+use image::io::{Limits, Reader};
+use image::RgbImage;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use termion::event::Event as TermEvent;
+use tui::{
+    layout::Rect,
+    style::{Color, Style},
+    text::{Span, Spans},
+};
+
+use crate::fsio::mime_type;
+use crate::tui::event::Event;
+
+/// Largest width and height of covers decoded, which keeps the memory used by
+/// a cover under a few hundred MB
+const MAX_DIMENSION: u32 = 16384;
+
+/// How covers are drawn
+#[derive(Clone, Copy, PartialEq)]
+pub enum Graphics {
+    Kitty,
+    Sixel,
+    /// Unicode half blocks, with two pixels per cell
+    Blocks,
+    None,
+}
+
+impl Graphics {
+    /// Get the graphics set in the config, detecting what the terminal
+    /// supports for `auto`
+    pub fn new(name: &str) -> Option<Graphics> {
+        match name {
+            "auto" => Some(Graphics::detect()),
+            "kitty" => Some(Graphics::Kitty),
+            "sixel" => Some(Graphics::Sixel),
+            "blocks" => Some(Graphics::Blocks),
+            "none" => Some(Graphics::None),
+            _ => None,
+        }
+    }
+
+    /// Guess the graphics protocol of the terminal from its environment.
+    /// Terminal multiplexers don't pass images through, so they get half
+    /// blocks.
+    fn detect() -> Graphics {
+        let var = |name: &str| std::env::var(name).unwrap_or_default();
+        let term = var("TERM");
+        if !var("TMUX").is_empty() || term.starts_with("screen") || term.starts_with("tmux") {
+            Graphics::Blocks
+        } else if !var("KITTY_WINDOW_ID").is_empty()
+            || term == "xterm-kitty"
+            || term == "xterm-ghostty"
+        {
+            Graphics::Kitty
+        } else if term.contains("sixel")
+            || term.starts_with("foot")
+            || term.starts_with("mlterm")
+            || var("TERM_PROGRAM") == "WezTerm"
+        {
+            Graphics::Sixel
+        } else {
+            Graphics::Blocks
+        }
+    }
+
+    /// Check if covers are drawn over the TUI rather than as part of it
+    pub fn is_protocol(&self) -> bool {
+        matches!(self, Graphics::Kitty | Graphics::Sixel)
+    }
+
+    /// Get the size in pixels of covers drawn in `area`
+    fn pixels(&self, area: Rect) -> (u32, u32) {
+        let (width, height) = (u32::from(area.width), u32::from(area.height));
+        let (cell_width, cell_height) = cell_size();
+        let (cell_width, cell_height) = (cell_width as u32, cell_height as u32);
+        match self {
+            Graphics::Kitty => (width * cell_width, height * cell_height),
+            // sixels are 6 pixels high, which would go past the area otherwise
+            Graphics::Sixel => (width * cell_width, height * cell_height / 6 * 6),
+            _ => (width, height * 2),
+        }
+    }
+}
+
+/// Get the size of terminal cells in pixels, assuming 8 by 16 when the
+/// terminal doesn't tell
+fn cell_size() -> (usize, usize) {
+    match (termion::terminal_size(), termion::terminal_size_pixels()) {
+        (Ok((columns, rows)), Ok((width, height)))
+            if columns > 0 && rows > 0 && width >= columns && height >= rows =>
+        {
+            ((width / columns) as usize, (height / rows) as usize)
+        }
+        _ => (8, 16),
+    }
+}
+
+/// Find the cover among the files of an item: a PNG or JPEG image named
+/// `cover`, which modules can download with the item, or the first image
+pub fn find_cover(files: &[PathBuf]) -> Option<PathBuf> {
+    let mut images = files
+        .iter()
+        .filter(|f| matches!(mime_type(f), "image/png" | "image/jpeg"));
+    let is_cover = |f: &&PathBuf| {
+        f.file_stem()
+            .and_then(OsStr::to_str)
+            .is_some_and(|s| s.eq_ignore_ascii_case("cover"))
+    };
+    images
+        .clone()
+        .find(is_cover)
+        .or_else(|| images.next())
+        .cloned()
+}
+
+/// Decode a PNG or JPEG image, recognized by its content rather than its
+/// extension, and scale it to fit in `width` by `height` pixels keeping its
+/// aspect ratio
+fn decode(file: &Path, width: u32, height: u32) -> Result<RgbImage, String> {
+    let mut reader = Reader::open(file)
+        .and_then(Reader::with_guessed_format)
+        .map_err(|e| e.to_string())?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|e| e.to_string())?;
+    Ok(image
+        .resize(width.max(1), height.max(1), FilterType::Triangle)
+        .to_rgb8())
+}
+
+/// Cover of the selected item, decoded and scaled in a thread of its own
+pub struct Covers {
+    tx: mpsc::Sender<Event<TermEvent>>,
+    /// Id of the latest request, which older ones check to stop early
+    latest: Arc<AtomicUsize>,
+    /// File and area of the latest request
+    request: Option<(PathBuf, Rect)>,
+    /// Cover of the latest request once loaded, or why it couldn't be
+    cover: Option<Result<RgbImage, String>>,
+    /// Request and area of the cover drawn with a graphics protocol
+    drawn: Option<(usize, Rect)>,
+}
+
+impl Covers {
+    pub fn new(tx: mpsc::Sender<Event<TermEvent>>) -> Covers {
+        Covers {
+            tx,
+            latest: Arc::new(AtomicUsize::new(0)),
+            request: None,
+            cover: None,
+            drawn: None,
+        }
+    }
+
+    /// Load `file` scaled to fit in `area`, unless it's loaded or loading
+    /// already. The cover is sent as an event once loaded.
+    pub fn load(&mut self, file: &Path, area: Rect, graphics: Graphics) {
+        if self
+            .request
+            .as_ref()
+            .is_some_and(|(f, a)| f == file && *a == area)
+        {
+            return;
+        }
+        let id = self.latest.fetch_add(1, Ordering::Relaxed) + 1;
+        self.request = Some((file.to_path_buf(), area));
+        self.cover = None;
+        let (width, height) = graphics.pixels(area);
+        let latest = self.latest.clone();
+        let tx = self.tx.clone();
+        let file = file.to_path_buf();
+        thread::spawn(move || {
+            // skip covers which were scrolled past
+            let current = || latest.load(Ordering::Relaxed) == id;
+            if !current() {
+                return;
+            }
+            let cover = decode(&file, width, height);
+            if current() {
+                let _ = tx.send(Event::Cover(id, cover));
+            }
+        });
+    }
+
+    /// Keep a loaded cover, unless another one was requested since
+    pub fn receive(&mut self, id: usize, cover: Result<RgbImage, String>) {
+        if id == self.latest.load(Ordering::Relaxed) {
+            self.cover = Some(cover);
+        }
+    }
+
+    /// Forget the cover, dropping the one loading
+    pub fn clear(&mut self) {
+        if self.request.is_some() {
+            self.latest.fetch_add(1, Ordering::Relaxed);
+            self.request = None;
+            self.cover = None;
+        }
+    }
+
+    /// Get the lines showing the cover of `file` in `area`: the cover itself
+    /// with half blocks, or whether it's loading or failed to
+    pub fn lines(&self, file: &Path, area: Rect, graphics: Graphics) -> Vec<Spans<'static>> {
+        let current = self
+            .request
+            .as_ref()
+            .is_some_and(|(f, a)| f == file && *a == area);
+        match &self.cover {
+            Some(Ok(image)) if current => match graphics {
+                Graphics::Blocks => blocks(image, area.width),
+                _ => Vec::new(),
+            },
+            Some(Err(e)) if current => vec![Spans::from(format!("Cover unavailable: {}", e))],
+            _ => vec![Spans::from("Loading cover…")],
+        }
+    }
+
+    /// Draw the cover with a graphics protocol if it's `visible` and loaded,
+    /// and remove the one drawn before if it's different. Sixel images are
+    /// part of the screen, so this returns true when it needs clearing.
+    pub fn show<W: Write>(
+        &mut self,
+        out: &mut W,
+        graphics: Graphics,
+        visible: bool,
+    ) -> std::io::Result<bool> {
+        let id = self.latest.load(Ordering::Relaxed);
+        let image = match (&self.cover, &self.request) {
+            (Some(Ok(image)), Some((_, area))) if visible && graphics.is_protocol() => {
+                Some((image, *area))
+            }
+            _ => None,
+        };
+        let wanted = image.as_ref().map(|(_, area)| (id, *area));
+        if wanted == self.drawn {
+            return Ok(false);
+        }
+        if self.drawn.is_some() {
+            self.drawn = None;
+            match graphics {
+                Graphics::Kitty => write!(out, "\x1b_Ga=d,q=2\x1b\\")?,
+                _ => return Ok(true),
+            }
+        }
+        if let Some((image, area)) = image {
+            // centered in the area, as the TUI doesn't know where it is
+            let columns = (image.width() as usize).div_ceil(cell_size().0) as u16;
+            let x = area.x + area.width.saturating_sub(columns) / 2;
+            let mut data: Vec<u8> = Vec::new();
+            write!(data, "\x1b[{};{}H", area.y + 1, x + 1)?;
+            match graphics {
+                Graphics::Kitty => kitty(&mut data, image)?,
+                _ => sixel(&mut data, image)?,
+            }
+            out.write_all(&data)?;
+        }
+        out.flush()?;
+        self.drawn = wanted;
+        Ok(false)
+    }
+}
+
+fn rgb(pixel: [u8; 3]) -> Color {
+    Color::Rgb(pixel[0], pixel[1], pixel[2])
+}
+
+/// Draw an image with upper half blocks, their foreground being the upper
+/// pixel and their background the lower one
+fn blocks(image: &RgbImage, width: u16) -> Vec<Spans<'static>> {
+    let padding = " ".repeat(u32::from(width).saturating_sub(image.width()) as usize / 2);
+    (0..image.height())
+        .step_by(2)
+        .map(|y| {
+            let mut spans = vec![Span::raw(padding.clone())];
+            for x in 0..image.width() {
+                let mut style = Style::default().fg(rgb(image.get_pixel(x, y).0));
+                if y + 1 < image.height() {
+                    style = style.bg(rgb(image.get_pixel(x, y + 1).0));
+                }
+                spans.push(Span::styled("▀", style));
+            }
+            Spans::from(spans)
+        })
+        .collect()
+}
+
+/// Write an image with the kitty graphics protocol, as RGB pixels sent in
+/// chunks of base64
+fn kitty(out: &mut Vec<u8>, image: &RgbImage) -> std::io::Result<()> {
+    let encoded = base64::encode(image.as_raw());
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        if i == 0 {
+            write!(
+                out,
+                "\x1b_Ga=T,f=24,s={},v={},C=1,q=2,m={};",
+                image.width(),
+                image.height(),
+                more
+            )?;
+        } else {
+            write!(out, "\x1b_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+    }
+    Ok(())
+}
+
+/// Write an image as sixels, with colors reduced to a 6x6x6 color cube
+fn sixel(out: &mut Vec<u8>, image: &RgbImage) -> std::io::Result<()> {
+    let level = |value: u8| (value as usize * 5 + 127) / 255;
+    let color = |pixel: [u8; 3]| level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2]);
+    let (width, height) = image.dimensions();
+    write!(out, "\x1bP0;1q\"1;1;{};{}", width, height)?;
+    for i in 0..216 {
+        write!(
+            out,
+            "#{};2;{};{};{}",
+            i,
+            i / 36 * 20,
+            i / 6 % 6 * 20,
+            i % 6 * 20
+        )?;
+    }
+    for top in (0..height).step_by(6) {
+        // pixels of each color in the 6 rows, by column
+        let mut bands: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+        for row in 0..6.min(height - top) {
+            for x in 0..width {
+                bands
+                    .entry(color(image.get_pixel(x, top + row).0))
+                    .or_insert_with(|| vec![0; width as usize])[x as usize] |= 1 << row;
+            }
+        }
+        for (i, (color, band)) in bands.iter().enumerate() {
+            if i > 0 {
+                write!(out, "$")?;
+            }
+            write!(out, "#{}", color)?;
+            let mut x = 0;
+            while x < band.len() {
+                let run = band[x..].iter().take_while(|b| **b == band[x]).count();
+                let sixel = (63 + band[x]) as char;
+                if run > 3 {
+                    write!(out, "!{}{}", run, sixel)?;
+                } else {
+                    write!(out, "{}", sixel.to_string().repeat(run))?;
+                }
+                x += run;
+            }
+        }
+        write!(out, "-")?;
+    }
+    write!(out, "\x1b\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const WHITE: [u8; 3] = [255, 255, 255];
+
+    fn image(width: u32, pixels: &[[u8; 3]]) -> RgbImage {
+        let height = pixels.len() as u32 / width;
+        RgbImage::from_raw(width, height, pixels.concat()).unwrap()
+    }
+
+    #[test]
+    fn decodes_and_scales_covers() {
+        // recognized by its content, whatever its extension
+        let file =
+            std::env::temp_dir().join(format!("bookshelf-test-cover-{}.jpg", std::process::id()));
+        image(2, &[RED; 8])
+            .save_with_format(&file, image::ImageFormat::Png)
+            .unwrap();
+        let cover = decode(&file, 10, 2).unwrap();
+        assert_eq!((1, 2), cover.dimensions());
+        assert_eq!(RED, cover.get_pixel(0, 1).0);
+
+        std::fs::write(&file, b"not an image").unwrap();
+        assert!(decode(&file, 10, 2).is_err());
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn finds_covers() {
+        let files = |names: &[&str]| -> Vec<PathBuf> { names.iter().map(PathBuf::from).collect() };
+        assert_eq!(
+            Some(PathBuf::from("Cover.JPG")),
+            find_cover(&files(&["book.epub", "1.png", "Cover.JPG"]))
+        );
+        assert_eq!(
+            Some(PathBuf::from("1.png")),
+            find_cover(&files(&["book.epub", "1.png", "cover.gif"]))
+        );
+        assert_eq!(None, find_cover(&files(&["book.epub"])));
+    }
+
+    #[test]
+    fn draws_half_blocks() {
+        let lines = blocks(&image(1, &[RED, WHITE, RED]), 3);
+        assert_eq!(2, lines.len());
+        let spans = &lines[0].0;
+        assert_eq!(" ", spans[0].content);
+        assert_eq!("▀", spans[1].content);
+        assert_eq!(Style::default().fg(rgb(RED)).bg(rgb(WHITE)), spans[1].style);
+        // the last row has no lower pixel
+        assert_eq!(Style::default().fg(rgb(RED)), lines[1].0[1].style);
+    }
+
+    #[test]
+    fn writes_sixels() {
+        let mut out: Vec<u8> = Vec::new();
+        sixel(&mut out, &image(2, &[RED, WHITE])).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("\x1bP0;1q\"1;1;2;1#0;2;0;0;0"));
+        // a band of each color, one pixel high
+        assert!(out.ends_with("#180@?$#215?@-\x1b\\"));
+
+        let mut out: Vec<u8> = Vec::new();
+        sixel(&mut out, &image(5, &[RED; 35])).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with("#180!5~-#180!5@-\x1b\\"));
+    }
+
+    #[test]
+    fn writes_kitty_chunks() {
+        let mut out: Vec<u8> = Vec::new();
+        kitty(&mut out, &image(1, &[RED])).unwrap();
+        assert_eq!(
+            b"\x1b_Ga=T,f=24,s=1,v=1,C=1,q=2,m=0;/wAA\x1b\\".to_vec(),
+            out
+        );
+
+        // 4096 bytes of base64 for each chunk
+        let mut out: Vec<u8> = Vec::new();
+        kitty(&mut out, &image(1024, &[WHITE; 1024 * 2])).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(2, out.matches("\x1b_G").count());
+        assert_eq!(1, out.matches("m=1;").count());
+        assert!(out.contains("\x1b\\\x1b_Gm=0;"));
+        assert!(out.ends_with("////\x1b\\"));
+    }
+}
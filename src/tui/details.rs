@@ -0,0 +1,227 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use termion::event::Event as TermEvent;
+use tui::{
+    style::{Modifier, Style},
+    text::{Span, Spans},
+};
+
+use crate::config::*;
+use crate::fsio::list_files;
+use crate::items::get_item_dir;
+use crate::module_handler::*;
+use crate::shelf::*;
+use crate::tui::cover::find_cover;
+use crate::tui::event::Event;
+use crate::tui::theme::Theme;
+
+/// Details of an item which are slow to get: its URL, which comes from its
+/// module, and its downloaded files
+pub struct Fetched {
+    url: Option<String>,
+    files: usize,
+    size: u64,
+    /// Image shown as the cover of the item
+    cover: Option<PathBuf>,
+}
+
+/// Details of the selected item, with the slow ones fetched in a thread of
+/// their own so moving the selection doesn't wait for them
+pub struct Details {
+    tx: mpsc::Sender<Event<TermEvent>>,
+    /// Id of the latest request, which older ones check to stop early
+    latest: Arc<AtomicUsize>,
+    item: Option<ItemDetails>,
+}
+
+impl Details {
+    pub fn new(tx: mpsc::Sender<Event<TermEvent>>) -> Details {
+        Details {
+            tx,
+            latest: Arc::new(AtomicUsize::new(0)),
+            item: None,
+        }
+    }
+
+    /// Get the details of an item, unless they're loaded or loading already.
+    /// The slow ones are sent as an event once fetched.
+    pub fn load(
+        &mut self,
+        config: &Config,
+        module_handler: &ModuleHandler,
+        module: &str,
+        code: &str,
+    ) {
+        if self
+            .item
+            .as_ref()
+            .is_some_and(|d| d.module == module && d.code == code)
+        {
+            return;
+        }
+        let id = self.latest.fetch_add(1, Ordering::Relaxed) + 1;
+        let dir = get_item_dir(&config.data_dir, module, code);
+        self.item = Some(ItemDetails {
+            module: module.to_string(),
+            code: code.to_string(),
+            media_type: module_handler.get_media_type(module).ok(),
+            dir: dir.clone(),
+            fetched: None,
+        });
+        let latest = self.latest.clone();
+        let tx = self.tx.clone();
+        let module_handler = module_handler.clone();
+        let (module, code) = (module.to_string(), code.to_string());
+        thread::spawn(move || {
+            // skip items which were scrolled past
+            if latest.load(Ordering::Relaxed) != id {
+                return;
+            }
+            let files = list_files(&dir);
+            let fetched = Fetched {
+                url: module_handler.derive_url(&module, &code).ok(),
+                files: files.len(),
+                size: files
+                    .iter()
+                    .filter_map(|f| std::fs::metadata(f).ok())
+                    .map(|m| m.len())
+                    .sum(),
+                cover: find_cover(&files),
+            };
+            let _ = tx.send(Event::Details(id, fetched));
+        });
+    }
+
+    /// Keep fetched details, unless another item was requested since
+    pub fn receive(&mut self, id: usize, fetched: Fetched) {
+        if id == self.latest.load(Ordering::Relaxed) {
+            if let Some(item) = self.item.as_mut() {
+                item.fetched = Some(fetched);
+            }
+        }
+    }
+
+    /// Forget the details, so they're fetched again when next loaded
+    pub fn clear(&mut self) {
+        if self.item.is_some() {
+            self.latest.fetch_add(1, Ordering::Relaxed);
+            self.item = None;
+        }
+    }
+
+    pub fn get(&self) -> Option<&ItemDetails> {
+        self.item.as_ref()
+    }
+}
+
+/// Details of an item, computed when the selection changes rather than on
+/// every frame
+pub struct ItemDetails {
+    pub module: String,
+    pub code: String,
+    media_type: Option<String>,
+    dir: PathBuf,
+    /// None until fetched
+    fetched: Option<Fetched>,
+}
+
+impl ItemDetails {
+    /// Get the image shown as the cover of the item, once its files are listed
+    pub fn cover(&self) -> Option<&PathBuf> {
+        self.fetched.as_ref().and_then(|f| f.cover.as_ref())
+    }
+
+    /// Get the lines describing the item, with its title, authors, genres and
+    /// favorite status read from the shelf so edits show up immediately
+    pub fn lines(&self, shelf: &Shelf, theme: &Theme) -> Vec<Spans<'static>> {
+        let mut lines: Vec<Spans> = Vec::new();
+        let item = match shelf.get_item(&self.module, &self.code) {
+            Some(item) => item,
+            None => return lines,
+        };
+        let (title, authors, genres) = item.export();
+        lines.push(Spans::from(Span::styled(
+            title.clone(),
+            Style::default().add_modifier(Modifier::BOLD),
+        )));
+        lines.push(Spans::default());
+        lines.push(heading(theme, "Authors"));
+        lines.extend(authors.iter().map(|a| Spans::from(format!("  {}", a))));
+        lines.push(heading(theme, "Genres"));
+        lines.extend(genres.iter().map(|g| Spans::from(format!("  {}", g))));
+        lines.push(Spans::default());
+        let favorite = if shelf.is_favorite(&self.module, &self.code) {
+            "yes"
+        } else {
+            "no"
+        };
+        lines.push(field(theme, "Favorite", favorite));
+        lines.push(field(theme, "Module", &self.module));
+        lines.push(field(theme, "Code", &self.code));
+        let url = match &self.fetched {
+            Some(fetched) => fetched.url.as_deref().unwrap_or("unavailable"),
+            None => "loading…",
+        };
+        lines.push(field(theme, "URL", url));
+        lines.push(field(
+            theme,
+            "Media type",
+            self.media_type.as_deref().unwrap_or("unknown"),
+        ));
+        lines.push(field(theme, "Directory", &self.dir.to_string_lossy()));
+        match &self.fetched {
+            None => lines.push(field(theme, "Files", "loading…")),
+            Some(fetched) if fetched.files == 0 => {
+                lines.push(field(theme, "Files", "not downloaded"))
+            }
+            Some(fetched) => lines.push(field(
+                theme,
+                "Files",
+                &format!("{} ({})", fetched.files, format_size(fetched.size)),
+            )),
+        }
+        lines
+    }
+}
+
+fn heading(theme: &Theme, name: &str) -> Spans<'static> {
+    Spans::from(Span::styled(name.to_string(), theme.header))
+}
+
+fn field(theme: &Theme, name: &str, value: &str) -> Spans<'static> {
+    Spans::from(vec![
+        Span::styled(format!("{}: ", name), theme.header),
+        Span::raw(value.to_string()),
+    ])
+}
+
+/// Format a size in bytes with binary units, e.g. `1.5 MiB`
+fn format_size(size: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", size, units[0])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!("0 B", format_size(0));
+        assert_eq!("1023 B", format_size(1023));
+        assert_eq!("1.5 KiB", format_size(1536));
+        assert_eq!("2.0 GiB", format_size(2 << 30));
+    }
+}
@@ -1,44 +1,57 @@
 use std::io;
 use std::sync::mpsc;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
 use std::thread;
 use std::time::Duration;
 
-use termion::event::Key;
+use image::RgbImage;
+use termion::event::Event as TermEvent;
 use termion::input::TermRead;
 
+use crate::tui::details::Fetched;
+use crate::tui::task::TaskEvent;
+
 pub enum Event<I> {
     Input(I),
     Tick,
+    /// Details of an item fetched for a request, by request id
+    Details(usize, Fetched),
+    /// Progress and results of background tasks
+    Task(TaskEvent),
+    /// Cover loaded for a request, by request id
+    Cover(usize, Result<RgbImage, String>),
 }
 
-/// A small event handler that wrap termion input and tick events. Each event
-/// type is handled in its own thread and returned to a common `Receiver`
+/// A small event handler that wrap termion key and mouse input and tick
+/// events. Each event type is handled in its own thread and returned to a
+/// common `Receiver`
 pub struct Events {
-    rx: mpsc::Receiver<Event<Key>>,
+    tx: mpsc::Sender<Event<TermEvent>>,
+    rx: mpsc::Receiver<Event<TermEvent>>,
+    #[allow(dead_code)]
     input_handle: thread::JoinHandle<()>,
-    ignore_exit_key: Arc<AtomicBool>,
+    #[allow(dead_code)]
     tick_handle: thread::JoinHandle<()>,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
-    pub exit_key: Key,
     pub tick_rate: Duration,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
-            exit_key: Key::Char('q'),
             tick_rate: Duration::from_millis(250),
         }
     }
 }
 
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Events {
     pub fn new() -> Events {
         Events::with_config(Config::default())
@@ -46,26 +59,20 @@ impl Events {
 
     pub fn with_config(config: Config) -> Events {
         let (tx, rx) = mpsc::channel();
-        let ignore_exit_key = Arc::new(AtomicBool::new(false));
         let input_handle = {
             let tx = tx.clone();
-            let ignore_exit_key = ignore_exit_key.clone();
             thread::spawn(move || {
                 let stdin = io::stdin();
-                for evt in stdin.keys() {
-                    if let Ok(key) = evt {
-                        if let Err(err) = tx.send(Event::Input(key)) {
-                            eprintln!("{}", err);
-                            return;
-                        }
-                        if !ignore_exit_key.load(Ordering::Relaxed) && key == config.exit_key {
-                            return;
-                        }
+                // Stops at the first event read after the receiver is dropped
+                for event in stdin.events().flatten() {
+                    if tx.send(Event::Input(event)).is_err() {
+                        return;
                     }
                 }
             })
         };
         let tick_handle = {
+            let tx = tx.clone();
             thread::spawn(move || loop {
                 if tx.send(Event::Tick).is_err() {
                     break;
@@ -74,22 +81,19 @@ impl Events {
             })
         };
         Events {
+            tx,
             rx,
-            ignore_exit_key,
             input_handle,
             tick_handle,
         }
     }
 
-    pub fn next(&self) -> Result<Event<Key>, mpsc::RecvError> {
-        self.rx.recv()
-    }
-
-    pub fn disable_exit_key(&mut self) {
-        self.ignore_exit_key.store(true, Ordering::Relaxed);
+    /// Get a sender for events from other threads, such as tasks
+    pub fn sender(&self) -> mpsc::Sender<Event<TermEvent>> {
+        self.tx.clone()
     }
 
-    pub fn enable_exit_key(&mut self) {
-        self.ignore_exit_key.store(false, Ordering::Relaxed);
+    pub fn next(&self) -> Result<Event<TermEvent>, mpsc::RecvError> {
+        self.rx.recv()
     }
 }
@@ -0,0 +1,181 @@
+use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A single line of text input, used for filters and editing. The cursor moves
+/// by grapheme clusters so characters made of several code points are edited
+/// as one.
+pub struct Input {
+    text: String,
+    /// Byte offset of the cursor, always on a grapheme boundary
+    cursor: usize,
+}
+
+impl Input {
+    /// Create an input with the cursor at the end of the text
+    pub fn new(text: &str) -> Input {
+        Input {
+            text: text.to_string(),
+            cursor: text.len(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Get the display width of the text before the cursor
+    pub fn cursor_width(&self) -> u16 {
+        self.text[..self.cursor].width() as u16
+    }
+
+    /// Apply a key to the input. Returns whether the text changed.
+    pub fn handle_key(&mut self, key: Key) -> bool {
+        match key {
+            Key::Char(c) => {
+                self.text.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+                true
+            }
+            Key::Backspace => {
+                let start = self.prev_boundary();
+                self.delete(start, self.cursor)
+            }
+            Key::Delete | Key::Ctrl('d') => {
+                let end = self.next_boundary();
+                self.delete(self.cursor, end)
+            }
+            Key::Ctrl('w') => {
+                let start = self.prev_word();
+                self.delete(start, self.cursor)
+            }
+            Key::Ctrl('u') => self.delete(0, self.cursor),
+            Key::Ctrl('k') => self.delete(self.cursor, self.text.len()),
+            Key::Left | Key::Ctrl('b') => {
+                self.cursor = self.prev_boundary();
+                false
+            }
+            Key::Right | Key::Ctrl('f') => {
+                self.cursor = self.next_boundary();
+                false
+            }
+            Key::Alt('b') => {
+                self.cursor = self.prev_word();
+                false
+            }
+            Key::Alt('f') => {
+                self.cursor = self.next_word();
+                false
+            }
+            Key::Home | Key::Ctrl('a') => {
+                self.cursor = 0;
+                false
+            }
+            Key::End | Key::Ctrl('e') => {
+                self.cursor = self.text.len();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Delete the text between two byte offsets, moving the cursor to the start
+    fn delete(&mut self, start: usize, end: usize) -> bool {
+        if start == end {
+            return false;
+        }
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+        true
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map(|g| self.cursor + g.len())
+            .unwrap_or(self.cursor)
+    }
+
+    /// Get the start of the word before the cursor
+    fn prev_word(&self) -> usize {
+        self.text[..self.cursor]
+            .split_word_bound_indices()
+            .rev()
+            .find(|(_, w)| !w.trim().is_empty())
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    /// Get the end of the word after the cursor
+    fn next_word(&self) -> usize {
+        self.text[self.cursor..]
+            .split_word_bound_indices()
+            .find(|(_, w)| !w.trim().is_empty())
+            .map(|(i, w)| self.cursor + i + w.len())
+            .unwrap_or_else(|| self.text.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_keys(input: &mut Input, keys: &[Key]) {
+        for key in keys {
+            input.handle_key(*key);
+        }
+    }
+
+    #[test]
+    fn edits_at_the_cursor() {
+        let mut input = Input::new("ac");
+        assert!(!input.handle_key(Key::Left));
+        assert!(input.handle_key(Key::Char('b')));
+        assert_eq!("abc", input.text());
+        type_keys(
+            &mut input,
+            &[Key::Home, Key::Delete, Key::End, Key::Char('d')],
+        );
+        assert_eq!("bcd", input.text());
+        assert!(!input.handle_key(Key::Delete));
+        type_keys(&mut input, &[Key::Ctrl('a'), Key::Right, Key::Ctrl('k')]);
+        assert_eq!("b", input.text());
+        type_keys(&mut input, &[Key::Char('e'), Key::Ctrl('u')]);
+        assert_eq!("", input.text());
+        assert!(!input.handle_key(Key::Backspace));
+    }
+
+    #[test]
+    fn moves_by_words() {
+        let mut input = Input::new("one  two three");
+        assert!(input.handle_key(Key::Ctrl('w')));
+        assert_eq!("one  two ", input.text());
+        type_keys(&mut input, &[Key::Alt('b'), Key::Alt('b')]);
+        assert_eq!(0, input.cursor_width());
+        input.handle_key(Key::Alt('f'));
+        assert_eq!(3, input.cursor_width());
+        input.handle_key(Key::Alt('f'));
+        assert_eq!(8, input.cursor_width());
+    }
+
+    #[test]
+    fn edits_graphemes_as_one() {
+        // e with a combining accent, and a wide character
+        let mut input = Input::new("e\u{301}字");
+        assert_eq!(3, input.cursor_width());
+        input.handle_key(Key::Left);
+        assert_eq!(1, input.cursor_width());
+        input.handle_key(Key::Backspace);
+        assert_eq!("字", input.text());
+        assert_eq!(0, input.cursor_width());
+    }
+}
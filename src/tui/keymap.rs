@@ -0,0 +1,644 @@
+use std::collections::BTreeMap;
+use termion::event::Key;
+
+/// Everything keys can be bound to in the TUI
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    Down,
+    Up,
+    HalfPageDown,
+    HalfPageUp,
+    Top,
+    Bottom,
+    FilterTitle,
+    FilterAuthors,
+    FilterGenres,
+    Search,
+    SearchBackward,
+    SearchNext,
+    SearchPrevious,
+    EditTitle,
+    EditAuthors,
+    EditGenres,
+    Add,
+    ToggleFavorite,
+    Delete,
+    Download,
+    Open,
+    ToggleDetails,
+    /// Cycle between the flat table and grouping by author, genre or module
+    Group,
+    Expand,
+    Collapse,
+    ToggleGroup,
+    YankItem,
+    YankUrl,
+    YankCitation,
+    YankBibtex,
+    /// Sort by the nth column, starting at 1
+    Sort(usize),
+    ToggleMark,
+    MarkRange,
+    MarkAll,
+    AddGenre,
+    RemoveGenre,
+    SetAuthors,
+    Undo,
+    Redo,
+    /// Clear marks, or filters if nothing is marked
+    Cancel,
+    /// Go to the nth tab, starting at 1
+    Tab(usize),
+    NextTab,
+    PreviousTab,
+    Reload,
+    Write,
+    Messages,
+    Tasks,
+    Help,
+}
+
+/// Every action, in the order they're listed in the help
+const ACTIONS: [Action; 65] = [
+    Action::Quit,
+    Action::Down,
+    Action::Up,
+    Action::HalfPageDown,
+    Action::HalfPageUp,
+    Action::Top,
+    Action::Bottom,
+    Action::FilterTitle,
+    Action::FilterAuthors,
+    Action::FilterGenres,
+    Action::Search,
+    Action::SearchBackward,
+    Action::SearchNext,
+    Action::SearchPrevious,
+    Action::EditTitle,
+    Action::EditAuthors,
+    Action::EditGenres,
+    Action::Add,
+    Action::ToggleFavorite,
+    Action::Delete,
+    Action::Download,
+    Action::Open,
+    Action::ToggleDetails,
+    Action::Group,
+    Action::Expand,
+    Action::Collapse,
+    Action::ToggleGroup,
+    Action::YankItem,
+    Action::YankUrl,
+    Action::YankCitation,
+    Action::YankBibtex,
+    Action::Sort(1),
+    Action::Sort(2),
+    Action::Sort(3),
+    Action::Sort(4),
+    Action::Sort(5),
+    Action::Sort(6),
+    Action::Sort(7),
+    Action::Sort(8),
+    Action::Sort(9),
+    Action::ToggleMark,
+    Action::MarkRange,
+    Action::MarkAll,
+    Action::AddGenre,
+    Action::RemoveGenre,
+    Action::SetAuthors,
+    Action::Undo,
+    Action::Redo,
+    Action::Cancel,
+    Action::Tab(1),
+    Action::Tab(2),
+    Action::Tab(3),
+    Action::Tab(4),
+    Action::Tab(5),
+    Action::Tab(6),
+    Action::Tab(7),
+    Action::Tab(8),
+    Action::Tab(9),
+    Action::NextTab,
+    Action::PreviousTab,
+    Action::Reload,
+    Action::Write,
+    Action::Messages,
+    Action::Tasks,
+    Action::Help,
+];
+
+impl Action {
+    /// Get the name of the action in the `keybindings` config section
+    pub fn name(&self) -> String {
+        let name = match self {
+            Action::Quit => "quit",
+            Action::Down => "down",
+            Action::Up => "up",
+            Action::HalfPageDown => "half_page_down",
+            Action::HalfPageUp => "half_page_up",
+            Action::Top => "top",
+            Action::Bottom => "bottom",
+            Action::FilterTitle => "filter_title",
+            Action::FilterAuthors => "filter_authors",
+            Action::FilterGenres => "filter_genres",
+            Action::Search => "search",
+            Action::SearchBackward => "search_backward",
+            Action::SearchNext => "search_next",
+            Action::SearchPrevious => "search_previous",
+            Action::EditTitle => "edit_title",
+            Action::EditAuthors => "edit_authors",
+            Action::EditGenres => "edit_genres",
+            Action::Add => "add",
+            Action::ToggleFavorite => "toggle_favorite",
+            Action::Delete => "delete",
+            Action::Download => "download",
+            Action::Open => "open",
+            Action::ToggleDetails => "toggle_details",
+            Action::Group => "group",
+            Action::Expand => "expand",
+            Action::Collapse => "collapse",
+            Action::ToggleGroup => "toggle_group",
+            Action::YankItem => "yank_item",
+            Action::YankUrl => "yank_url",
+            Action::YankCitation => "yank_citation",
+            Action::YankBibtex => "yank_bibtex",
+            Action::Sort(n) => return format!("sort_{}", n),
+            Action::ToggleMark => "toggle_mark",
+            Action::MarkRange => "mark_range",
+            Action::MarkAll => "mark_all",
+            Action::AddGenre => "add_genre",
+            Action::RemoveGenre => "remove_genre",
+            Action::SetAuthors => "set_authors",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::Cancel => "cancel",
+            Action::Tab(n) => return format!("tab_{}", n),
+            Action::NextTab => "next_tab",
+            Action::PreviousTab => "previous_tab",
+            Action::Reload => "reload",
+            Action::Write => "write",
+            Action::Messages => "messages",
+            Action::Tasks => "tasks",
+            Action::Help => "help",
+        };
+        name.to_string()
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().find(|a| a.name() == name).copied()
+    }
+
+    /// Describe the action for the help
+    pub fn description(&self) -> String {
+        let description = match self {
+            Action::Quit => "quit",
+            Action::Down => "move down",
+            Action::Up => "move up",
+            Action::HalfPageDown => "move down 50%",
+            Action::HalfPageUp => "move up 50%",
+            Action::Top => "go to top",
+            Action::Bottom => "go to bottom",
+            Action::FilterTitle => "filter by title regex",
+            Action::FilterAuthors => "filter by authors",
+            Action::FilterGenres => "filter by genres",
+            Action::Search => "search forward",
+            Action::SearchBackward => "search backward",
+            Action::SearchNext => "go to next match",
+            Action::SearchPrevious => "go to previous match",
+            Action::EditTitle => "edit title",
+            Action::EditAuthors => "edit authors",
+            Action::EditGenres => "edit genres",
+            Action::Add => "add item by URL or code",
+            Action::ToggleFavorite => "toggle favorite",
+            Action::Delete => "delete",
+            Action::Download => "download",
+            Action::Open => "open",
+            Action::ToggleDetails => "toggle details pane",
+            Action::Group => "group by author, genre, module or nothing",
+            Action::Expand => "expand group",
+            Action::Collapse => "collapse group",
+            Action::ToggleGroup => "expand/collapse group",
+            Action::YankItem => "yank module and code",
+            Action::YankUrl => "yank URL",
+            Action::YankCitation => "yank citation",
+            Action::YankBibtex => "yank BibTeX entry",
+            Action::Sort(n) => return format!("sort by column {}", n),
+            Action::ToggleMark => "mark/unmark item",
+            Action::MarkRange => "mark range",
+            Action::MarkAll => "mark/unmark all items shown",
+            Action::AddGenre => "add genre",
+            Action::RemoveGenre => "remove genre",
+            Action::SetAuthors => "set authors",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::Cancel => "clear marks or filters",
+            Action::Tab(n) => return format!("go to tab {}", n),
+            Action::NextTab => "go to next tab",
+            Action::PreviousTab => "go to previous tab",
+            Action::Reload => "reload index",
+            Action::Write => "write index",
+            Action::Messages => "show message log",
+            Action::Tasks => "show and cancel background tasks",
+            Action::Help => "show this help",
+        };
+        description.to_string()
+    }
+
+    /// Get the keys bound to the action by default
+    fn default_keys(&self) -> Vec<&'static str> {
+        match self {
+            Action::Quit => vec!["q"],
+            Action::Down => vec!["j", "<Down>"],
+            Action::Up => vec!["k", "<Up>"],
+            Action::HalfPageDown => vec!["<C-d>"],
+            Action::HalfPageUp => vec!["<C-u>"],
+            Action::Top => vec!["<Home>"],
+            Action::Bottom => vec!["<End>"],
+            Action::FilterTitle => vec!["ft"],
+            Action::FilterAuthors => vec!["fa"],
+            Action::FilterGenres => vec!["fg"],
+            Action::Search => vec!["/"],
+            Action::SearchBackward => vec!["?"],
+            Action::SearchNext => vec!["n"],
+            Action::SearchPrevious => vec!["N"],
+            Action::EditTitle => vec!["et"],
+            Action::EditAuthors => vec!["ea"],
+            Action::EditGenres => vec!["eg"],
+            Action::Add => vec!["a"],
+            Action::ToggleFavorite => vec!["F"],
+            Action::Delete => vec!["D"],
+            Action::Download => vec!["d"],
+            Action::Open => vec!["o"],
+            Action::ToggleDetails => vec!["i"],
+            Action::Group => vec!["v"],
+            Action::Expand => vec!["l", "<Right>"],
+            Action::Collapse => vec!["h", "<Left>"],
+            Action::ToggleGroup => vec!["<Enter>"],
+            Action::YankItem => vec!["yy"],
+            Action::YankUrl => vec!["yu"],
+            Action::YankCitation => vec!["yc"],
+            Action::YankBibtex => vec!["yb"],
+            Action::Sort(1) => vec!["s1"],
+            Action::Sort(2) => vec!["s2"],
+            Action::Sort(3) => vec!["s3"],
+            Action::Sort(4) => vec!["s4"],
+            Action::Sort(5) => vec!["s5"],
+            Action::Sort(6) => vec!["s6"],
+            Action::Sort(7) => vec!["s7"],
+            Action::Sort(8) => vec!["s8"],
+            Action::Sort(9) => vec!["s9"],
+            Action::Sort(_) => vec![],
+            Action::ToggleMark => vec!["<Space>"],
+            Action::MarkRange => vec!["V"],
+            Action::MarkAll => vec!["A"],
+            Action::AddGenre => vec!["+"],
+            Action::RemoveGenre => vec!["-"],
+            Action::SetAuthors => vec!["="],
+            Action::Undo => vec!["u"],
+            Action::Redo => vec!["<C-r>"],
+            Action::Cancel => vec!["<Esc>"],
+            Action::Tab(1) => vec!["1"],
+            Action::Tab(2) => vec!["2"],
+            Action::Tab(3) => vec!["3"],
+            Action::Tab(4) => vec!["4"],
+            Action::Tab(5) => vec!["5"],
+            Action::Tab(6) => vec!["6"],
+            Action::Tab(7) => vec!["7"],
+            Action::Tab(8) => vec!["8"],
+            Action::Tab(9) => vec!["9"],
+            Action::Tab(_) => vec![],
+            Action::NextTab => vec!["gt"],
+            Action::PreviousTab => vec!["gT"],
+            Action::Reload => vec!["r"],
+            Action::Write => vec!["w"],
+            Action::Messages => vec!["L"],
+            Action::Tasks => vec!["T"],
+            Action::Help => vec!["<F1>", "g?"],
+        }
+    }
+}
+
+/// Result of feeding a key to the keymap
+pub enum Lookup {
+    Action(Action),
+    /// The keys so far start a longer binding
+    Pending,
+    /// The keys aren't bound to anything
+    Unbound,
+}
+
+/// Maps key sequences to actions, keeping track of keys typed so far
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Action)>,
+    pending: Vec<Key>,
+}
+
+impl Keymap {
+    /// Build the keymap from the `keybindings` config section, mapping action
+    /// names to key sequences. Actions in the section replace their default
+    /// bindings. Returns every error found, including sequences bound to more
+    /// than one action and sequences starting a longer one, which could never
+    /// be reached.
+    pub fn new(config: &BTreeMap<String, Vec<String>>) -> Result<Keymap, Vec<String>> {
+        let mut errors: Vec<String> = Vec::new();
+        for name in config.keys() {
+            if Action::from_name(name).is_none() {
+                errors.push(format!("Unknown action: {}", name));
+            }
+        }
+        let mut bindings: Vec<(Vec<Key>, Action)> = Vec::new();
+        for action in ACTIONS.iter() {
+            let sequences: Vec<String> = match config.get(&action.name()) {
+                Some(sequences) => sequences.clone(),
+                None => action
+                    .default_keys()
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+            };
+            for sequence in sequences {
+                match parse_keys(&sequence) {
+                    Some(keys) if !keys.is_empty() => bindings.push((keys, *action)),
+                    _ => errors.push(format!("Invalid keys for {}: {}", action.name(), sequence)),
+                }
+            }
+        }
+        for (i, (keys, action)) in bindings.iter().enumerate() {
+            for (other_keys, other) in bindings[i + 1..].iter() {
+                if keys == other_keys {
+                    errors.push(format!(
+                        "{} is bound to both {} and {}",
+                        display_keys(keys),
+                        action.name(),
+                        other.name()
+                    ));
+                } else if other_keys.starts_with(keys) || keys.starts_with(other_keys) {
+                    let (short, long) = if keys.len() < other_keys.len() {
+                        ((keys, action), (other_keys, other))
+                    } else {
+                        ((other_keys, other), (keys, action))
+                    };
+                    errors.push(format!(
+                        "{} ({}) hides {} ({})",
+                        display_keys(short.0),
+                        short.1.name(),
+                        display_keys(long.0),
+                        long.1.name()
+                    ));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(Keymap {
+                bindings,
+                pending: Vec::new(),
+            })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Feed a key, returning the action it completes if any
+    pub fn lookup(&mut self, key: Key) -> Lookup {
+        self.pending.push(key);
+        if let Some((_, action)) = self.bindings.iter().find(|(k, _)| *k == self.pending) {
+            self.pending.clear();
+            return Lookup::Action(*action);
+        }
+        if self
+            .bindings
+            .iter()
+            .any(|(k, _)| k.starts_with(&self.pending))
+        {
+            Lookup::Pending
+        } else {
+            self.pending.clear();
+            Lookup::Unbound
+        }
+    }
+
+    /// Get the keys typed so far of an unfinished sequence
+    pub fn pending(&self) -> String {
+        display_keys(&self.pending)
+    }
+
+    /// Get the first keys bound to an action, if any, to show in prompts
+    pub fn keys(&self, action: Action) -> Option<String> {
+        self.bindings
+            .iter()
+            .find(|(_, a)| *a == action)
+            .map(|(k, _)| display_keys(k))
+    }
+
+    /// Get the keys bound to each action, in help order
+    pub fn help(&self) -> Vec<(String, String)> {
+        ACTIONS
+            .iter()
+            .filter_map(|action| {
+                let keys: Vec<String> = self
+                    .bindings
+                    .iter()
+                    .filter(|(_, a)| a == action)
+                    .map(|(k, _)| display_keys(k))
+                    .collect();
+                if keys.is_empty() {
+                    None
+                } else {
+                    Some((keys.join(", "), action.description()))
+                }
+            })
+            .collect()
+    }
+}
+
+/// Parse a key sequence such as `ft`, `<C-d>` or `g<Home>`. Special keys are
+/// written between `<` and `>`, and `<lt>` is a literal `<`.
+pub fn parse_keys(sequence: &str) -> Option<Vec<Key>> {
+    let mut keys: Vec<Key> = Vec::new();
+    let mut chars = sequence.chars();
+    while let Some(c) = chars.next() {
+        if c == '<' {
+            let mut name = String::new();
+            loop {
+                match chars.next()? {
+                    '>' => break,
+                    c => name.push(c),
+                }
+            }
+            keys.push(parse_special(&name)?);
+        } else {
+            keys.push(Key::Char(c));
+        }
+    }
+    Some(keys)
+}
+
+fn parse_special(name: &str) -> Option<Key> {
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(c) = name.strip_prefix("C-").and_then(single) {
+        return Some(Key::Ctrl(c));
+    }
+    if let Some(c) = name.strip_prefix("A-").and_then(single) {
+        return Some(Key::Alt(c));
+    }
+    if let Some(n) = name.strip_prefix('F').and_then(|n| n.parse::<u8>().ok()) {
+        return Some(Key::F(n));
+    }
+    match name {
+        "Up" => Some(Key::Up),
+        "Down" => Some(Key::Down),
+        "Left" => Some(Key::Left),
+        "Right" => Some(Key::Right),
+        "Home" => Some(Key::Home),
+        "End" => Some(Key::End),
+        "PageUp" => Some(Key::PageUp),
+        "PageDown" => Some(Key::PageDown),
+        "Insert" => Some(Key::Insert),
+        "Delete" => Some(Key::Delete),
+        "BackTab" => Some(Key::BackTab),
+        "Backspace" => Some(Key::Backspace),
+        "Esc" => Some(Key::Esc),
+        "Enter" => Some(Key::Char('\n')),
+        "Tab" => Some(Key::Char('\t')),
+        "Space" => Some(Key::Char(' ')),
+        "lt" => Some(Key::Char('<')),
+        _ => None,
+    }
+}
+
+/// Write a key sequence the way it's written in the config
+pub fn display_keys(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|key| match key {
+            Key::Char('\n') => String::from("<Enter>"),
+            Key::Char('\t') => String::from("<Tab>"),
+            Key::Char(' ') => String::from("<Space>"),
+            Key::Char('<') => String::from("<lt>"),
+            Key::Char(c) => c.to_string(),
+            Key::Ctrl(c) => format!("<C-{}>", c),
+            Key::Alt(c) => format!("<A-{}>", c),
+            Key::F(n) => format!("<F{}>", n),
+            Key::Up => String::from("<Up>"),
+            Key::Down => String::from("<Down>"),
+            Key::Left => String::from("<Left>"),
+            Key::Right => String::from("<Right>"),
+            Key::Home => String::from("<Home>"),
+            Key::End => String::from("<End>"),
+            Key::PageUp => String::from("<PageUp>"),
+            Key::PageDown => String::from("<PageDown>"),
+            Key::Insert => String::from("<Insert>"),
+            Key::Delete => String::from("<Delete>"),
+            Key::BackTab => String::from("<BackTab>"),
+            Key::Backspace => String::from("<Backspace>"),
+            Key::Esc => String::from("<Esc>"),
+            _ => String::from("<?>"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(bindings: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        bindings
+            .iter()
+            .map(|(name, keys)| {
+                (
+                    name.to_string(),
+                    keys.iter().map(|k| k.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_key_sequences() {
+        assert_eq!(Some(vec![Key::Char('f'), Key::Char('t')]), parse_keys("ft"));
+        assert_eq!(
+            Some(vec![Key::Ctrl('d'), Key::Alt('x'), Key::F(12), Key::Home]),
+            parse_keys("<C-d><A-x><F12><Home>")
+        );
+        assert_eq!(
+            Some(vec![Key::Char('<'), Key::Char(' '), Key::Char('\n')]),
+            parse_keys("<lt><Space><Enter>")
+        );
+        assert_eq!(Some(Vec::new()), parse_keys(""));
+        assert_eq!(None, parse_keys("<C-dd>"));
+        assert_eq!(None, parse_keys("<Nope>"));
+        assert_eq!(None, parse_keys("<Home"));
+        let keys = parse_keys("g<lt><C-r><Space>").unwrap();
+        assert_eq!("g<lt><C-r><Space>", display_keys(&keys));
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        assert!(Keymap::new(&BTreeMap::new()).is_ok());
+        for action in ACTIONS.iter() {
+            assert_eq!(Some(*action), Action::from_name(&action.name()));
+        }
+    }
+
+    #[test]
+    fn reports_conflicts() {
+        let errors = Keymap::new(&config(&[
+            ("quit", &["n"]),
+            ("help", &["f"]),
+            ("nope", &["x"]),
+            ("undo", &["<Bad>"]),
+        ]))
+        .err()
+        .unwrap();
+        assert_eq!(
+            vec![
+                "Unknown action: nope",
+                "Invalid keys for undo: <Bad>",
+                "n is bound to both quit and search_next",
+                "f (help) hides ft (filter_title)",
+                "f (help) hides fa (filter_authors)",
+                "f (help) hides fg (filter_genres)",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn looks_up_sequences() {
+        let mut keymap = Keymap::new(&config(&[("quit", &["q", "<C-c>"])])).unwrap();
+        assert!(matches!(
+            keymap.lookup(Key::Ctrl('c')),
+            Lookup::Action(Action::Quit)
+        ));
+        assert!(matches!(keymap.lookup(Key::Char('f')), Lookup::Pending));
+        assert_eq!("f", keymap.pending());
+        assert!(matches!(
+            keymap.lookup(Key::Char('a')),
+            Lookup::Action(Action::FilterAuthors)
+        ));
+        assert!(matches!(keymap.lookup(Key::Char('f')), Lookup::Pending));
+        assert!(matches!(keymap.lookup(Key::Char('z')), Lookup::Unbound));
+        assert_eq!("", keymap.pending());
+        assert!(matches!(
+            keymap.lookup(Key::Char('?')),
+            Lookup::Action(Action::SearchBackward)
+        ));
+        assert!(matches!(
+            keymap.lookup(Key::F(1)),
+            Lookup::Action(Action::Help)
+        ));
+        assert!(matches!(keymap.lookup(Key::Char('g')), Lookup::Pending));
+        assert!(matches!(
+            keymap.lookup(Key::Char('?')),
+            Lookup::Action(Action::Help)
+        ));
+        assert_eq!(Some(String::from("<F1>")), keymap.keys(Action::Help));
+        assert_eq!(Some(String::from("?")), keymap.keys(Action::SearchBackward));
+    }
+}
@@ -0,0 +1,58 @@
+/// Number of messages kept in the log
+const MAX_MESSAGES: usize = 1000;
+
+/// Errors and results of actions, the last of which is shown in the status bar
+#[derive(Default)]
+pub struct Messages {
+    log: Vec<String>,
+    /// Whether the last message is shown in the status bar
+    shown: bool,
+}
+
+impl Messages {
+    pub fn push(&mut self, message: String) {
+        if self.log.len() >= MAX_MESSAGES {
+            self.log.remove(0);
+        }
+        self.log.push(message);
+        self.shown = true;
+    }
+
+    /// Stop showing the last message in the status bar, keeping it in the log
+    pub fn hide(&mut self) {
+        self.shown = false;
+    }
+
+    /// Get the message shown in the status bar
+    pub fn current(&self) -> &str {
+        match self.log.last() {
+            Some(message) if self.shown => message,
+            _ => "",
+        }
+    }
+
+    pub fn log(&self) -> &[String] {
+        &self.log
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_a_bounded_log() {
+        let mut messages = Messages::default();
+        assert_eq!("", messages.current());
+        messages.push(String::from("first"));
+        messages.hide();
+        assert_eq!("", messages.current());
+        assert_eq!(&[String::from("first")], messages.log());
+        for i in 0..MAX_MESSAGES {
+            messages.push(i.to_string());
+        }
+        assert_eq!(MAX_MESSAGES, messages.log().len());
+        assert_eq!("0", messages.log()[0]);
+        assert_eq!((MAX_MESSAGES - 1).to_string(), messages.current());
+    }
+}
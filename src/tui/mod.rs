@@ -1,2 +1,12 @@
+pub mod column;
+pub mod cover;
+pub mod details;
 pub mod event;
+pub mod input;
+pub mod keymap;
+pub mod messages;
+pub mod tab;
+pub mod task;
+pub mod theme;
+pub mod tree;
 pub mod ui;
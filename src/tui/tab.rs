@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::shelf::*;
+
+/// Items shown in a tab of the TUI, before the filters typed in it. Criteria
+/// are the same as for `bookshelf search`.
+#[derive(Clone, Default)]
+pub struct Tab {
+    pub name: String,
+    module: Option<String>,
+    title: Option<String>,
+    authors: Option<String>,
+    genres: Option<String>,
+    favorites: bool,
+}
+
+impl Tab {
+    /// Get the tabs shown when none are configured
+    pub fn defaults() -> Vec<Tab> {
+        vec![
+            Tab {
+                name: String::from("All"),
+                ..Tab::default()
+            },
+            Tab {
+                name: String::from("Favorites"),
+                favorites: true,
+                ..Tab::default()
+            },
+        ]
+    }
+
+    /// Build a tab from an entry of the `tabs` config section, with its
+    /// `name` and criteria
+    pub fn new(config: &BTreeMap<String, String>) -> Result<Tab, Vec<String>> {
+        let mut errors: Vec<String> = Vec::new();
+        let mut tab = Tab::default();
+        match config.get("name") {
+            Some(name) if !name.is_empty() => tab.name = name.clone(),
+            _ => errors.push(String::from("Tab without a name")),
+        }
+        let label = if tab.name.is_empty() {
+            "unnamed tab"
+        } else {
+            tab.name.as_str()
+        };
+        for (key, value) in config.iter().filter(|(k, _)| *k != "name") {
+            match key.as_str() {
+                "module" => tab.module = Some(value.clone()),
+                "title" => tab.title = Some(value.clone()),
+                "authors" => tab.authors = Some(value.clone()),
+                "genres" => tab.genres = Some(value.clone()),
+                "favorites" => match value.parse() {
+                    Ok(favorites) => tab.favorites = favorites,
+                    Err(_) => errors.push(format!("Invalid favorites for {}: {}", label, value)),
+                },
+                _ => errors.push(format!("Unknown tab criteria for {}: {}", label, key)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(tab)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Get the items of the tab
+    pub fn search(&self, shelf: &Shelf) -> Result<BTreeSet<(String, String)>, regex::Error> {
+        shelf.search_item(
+            self.module.as_deref(),
+            self.title.as_deref(),
+            self.authors.as_deref(),
+            self.genres.as_deref(),
+            None,
+            false,
+            self.favorites,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelf::test_util::shelf;
+
+    fn config(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn keys(items: &[(&str, &str)]) -> BTreeSet<(String, String)> {
+        items
+            .iter()
+            .map(|(m, c)| (m.to_string(), c.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn checks_criteria() {
+        let tab = Tab::new(&config(&[("name", "SF"), ("genres", "sf")])).unwrap();
+        assert_eq!("SF", tab.name);
+        assert_eq!(
+            Err(vec![String::from("Tab without a name")]),
+            Tab::new(&config(&[("module", "a")])).map(|t| t.name)
+        );
+        assert_eq!(
+            Err(vec![
+                String::from("Invalid favorites for Mine: yes"),
+                String::from("Unknown tab criteria for Mine: year"),
+            ]),
+            Tab::new(&config(&[
+                ("name", "Mine"),
+                ("favorites", "yes"),
+                ("year", "1965")
+            ]))
+            .map(|t| t.name)
+        );
+        assert_eq!(
+            Err(vec![
+                String::from("Tab without a name"),
+                String::from("Unknown tab criteria for unnamed tab: year"),
+            ]),
+            Tab::new(&config(&[("year", "1965")])).map(|t| t.name)
+        );
+    }
+
+    #[test]
+    fn searches_items() {
+        let shelf = shelf();
+        let defaults = Tab::defaults();
+        assert_eq!(
+            keys(&[("a", "1"), ("a", "2"), ("b", "3")]),
+            defaults[0].search(&shelf).unwrap()
+        );
+        assert_eq!(keys(&[("a", "2")]), defaults[1].search(&shelf).unwrap());
+        let tab = Tab::new(&config(&[
+            ("name", "A"),
+            ("module", "a"),
+            ("title", "Dune"),
+        ]))
+        .unwrap();
+        assert_eq!(keys(&[("a", "1")]), tab.search(&shelf).unwrap());
+        let tab = Tab::new(&config(&[("name", "Bad"), ("title", "(")])).unwrap();
+        assert!(tab.search(&shelf).is_err());
+    }
+}
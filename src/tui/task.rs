@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use termion::event::Event as TermEvent;
+
+use crate::shelf::*;
+use crate::tui::event::Event;
+
+/// Results sent by jobs as they go
+pub enum Output {
+    /// Items fetched to be added, described by what was added
+    Added(String, Result<Shelf, String>),
+    /// Item downloaded, or why it couldn't be
+    Downloaded((String, String), Result<(), String>),
+    /// Queued download skipped because the task was cancelled
+    Skipped((String, String)),
+    /// Index read to be reloaded
+    Loaded(Result<Shelf, String>),
+}
+
+/// Events sent by tasks to the TUI, by task id
+pub enum TaskEvent {
+    /// Number of steps done
+    Progress(usize, usize),
+    Output(usize, Output),
+    Finished(usize),
+}
+
+/// Handle given to jobs to report progress and results
+pub struct Progress {
+    id: usize,
+    tx: mpsc::Sender<Event<TermEvent>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Progress {
+    /// Report the number of steps done
+    pub fn step(&self, done: usize) {
+        let _ = self
+            .tx
+            .send(Event::Task(TaskEvent::Progress(self.id, done)));
+    }
+
+    /// Send a result. Returns false if the TUI has exited, in which case the
+    /// job should stop.
+    pub fn send(&self, output: Output) -> bool {
+        self.tx
+            .send(Event::Task(TaskEvent::Output(self.id, output)))
+            .is_ok()
+    }
+
+    /// Check if the task was cancelled. Jobs check this between steps, so the
+    /// running step finishes.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// A running task
+pub struct Task {
+    pub name: String,
+    pub done: usize,
+    pub total: usize,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Task {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Draw the progress as a bar `width` cells wide
+    pub fn bar(&self, width: usize) -> String {
+        let filled = (self.done * width)
+            .checked_div(self.total)
+            .unwrap_or(0)
+            .min(width);
+        format!("{}{}", "█".repeat(filled), "░".repeat(width - filled))
+    }
+}
+
+/// Runs jobs in their own threads, which report back through the events of
+/// the TUI so it keeps responding to input
+pub struct Tasks {
+    tx: mpsc::Sender<Event<TermEvent>>,
+    next_id: usize,
+    pub running: BTreeMap<usize, Task>,
+}
+
+impl Tasks {
+    pub fn new(tx: mpsc::Sender<Event<TermEvent>>) -> Tasks {
+        Tasks {
+            tx,
+            next_id: 0,
+            running: BTreeMap::new(),
+        }
+    }
+
+    /// Run a job of `total` steps in the background
+    pub fn spawn<F>(&mut self, name: String, total: usize, job: F)
+    where
+        F: FnOnce(&Progress) + Send + 'static,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let progress = Progress {
+            id,
+            tx: self.tx.clone(),
+            cancelled: cancelled.clone(),
+        };
+        thread::spawn(move || {
+            job(&progress);
+            let _ = progress.tx.send(Event::Task(TaskEvent::Finished(id)));
+        });
+        self.running.insert(
+            id,
+            Task {
+                name,
+                done: 0,
+                total,
+                cancelled,
+            },
+        );
+    }
+
+    /// Update progress from an event, returning the task if it finished
+    pub fn update(&mut self, event: &TaskEvent) -> Option<Task> {
+        match event {
+            TaskEvent::Progress(id, done) => {
+                if let Some(task) = self.running.get_mut(id) {
+                    task.done = *done;
+                }
+                None
+            }
+            TaskEvent::Output(..) => None,
+            TaskEvent::Finished(id) => self.running.remove(id),
+        }
+    }
+
+    /// Ask a task to stop after its running step
+    pub fn cancel(&self, id: usize) {
+        if let Some(task) = self.running.get(&id) {
+            task.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next(rx: &mpsc::Receiver<Event<TermEvent>>) -> TaskEvent {
+        match rx.recv() {
+            Ok(Event::Task(event)) => event,
+            _ => panic!("expected a task event"),
+        }
+    }
+
+    #[test]
+    fn reports_progress_and_cancellation() {
+        let (tx, rx) = mpsc::channel();
+        let mut tasks = Tasks::new(tx);
+        let (start_tx, start_rx) = mpsc::channel::<()>();
+        tasks.spawn(String::from("job"), 2, move |progress| {
+            progress.step(1);
+            // wait to be cancelled
+            start_rx.recv().unwrap();
+            progress.send(Output::Skipped((
+                String::new(),
+                progress.is_cancelled().to_string(),
+            )));
+        });
+        assert_eq!("░░░░", tasks.running[&0].bar(4));
+
+        let event = next(&rx);
+        assert!(matches!(event, TaskEvent::Progress(0, 1)));
+        assert!(tasks.update(&event).is_none());
+        assert_eq!("██░░", tasks.running[&0].bar(4));
+
+        tasks.cancel(0);
+        assert!(tasks.running[&0].is_cancelled());
+        start_tx.send(()).unwrap();
+        match next(&rx) {
+            TaskEvent::Output(0, Output::Skipped((_, cancelled))) => assert_eq!("true", cancelled),
+            _ => panic!("expected the output of the job"),
+        }
+        let event = next(&rx);
+        assert_eq!("job", tasks.update(&event).unwrap().name);
+        assert!(tasks.running.is_empty());
+    }
+}
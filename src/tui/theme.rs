@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use tui::style::{Color, Modifier, Style};
+
+/// Styles used to render the TUI
+#[derive(Clone, Copy)]
+pub struct Theme {
+    /// Table headers and labels of the details pane
+    pub header: Style,
+    pub selected: Style,
+    /// Rows marked for bulk actions
+    pub marked: Style,
+    pub favorite: Style,
+    /// Rows of items with no downloaded files
+    pub missing: Style,
+    /// Rows of items whose last download failed
+    pub failed: Style,
+    pub status: Style,
+    pub error: Style,
+    /// Questions such as delete confirmations
+    pub prompt: Style,
+    /// Text matching the search
+    pub search: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            header: Style::default().fg(Color::Red),
+            selected: Style::default().add_modifier(Modifier::REVERSED),
+            marked: Style::default().fg(Color::Yellow),
+            favorite: Style::default().fg(Color::Magenta),
+            missing: Style::default().add_modifier(Modifier::DIM),
+            failed: Style::default().fg(Color::Red),
+            status: Style::default(),
+            error: Style::default().fg(Color::Red),
+            prompt: Style::default().fg(Color::Yellow),
+            search: Style::default().fg(Color::Black).bg(Color::Yellow),
+        }
+    }
+}
+
+impl Theme {
+    /// Get a built-in theme by name
+    pub fn builtin(name: &str) -> Option<Theme> {
+        match name {
+            "default" => Some(Theme::default()),
+            "light" => Some(Theme {
+                header: Style::default().fg(Color::Blue),
+                selected: Style::default().bg(Color::Gray).fg(Color::Black),
+                marked: Style::default().fg(Color::Magenta),
+                favorite: Style::default().fg(Color::Red),
+                missing: Style::default().fg(Color::DarkGray),
+                failed: Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                status: Style::default(),
+                error: Style::default().fg(Color::Red),
+                prompt: Style::default().fg(Color::Blue),
+                search: Style::default().bg(Color::LightYellow),
+            }),
+            "monochrome" => Some(Theme {
+                header: Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                selected: Style::default().add_modifier(Modifier::REVERSED),
+                marked: Style::default().add_modifier(Modifier::BOLD),
+                favorite: Style::default(),
+                missing: Style::default().add_modifier(Modifier::DIM),
+                failed: Style::default().add_modifier(Modifier::ITALIC),
+                status: Style::default(),
+                error: Style::default().add_modifier(Modifier::BOLD),
+                prompt: Style::default().add_modifier(Modifier::BOLD),
+                search: Style::default().add_modifier(Modifier::UNDERLINED),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Build the theme from the `theme` config section. `name` picks the
+    /// built-in theme to start from, which is `monochrome` when `NO_COLOR` is
+    /// set and `default` otherwise, and other entries replace its styles.
+    pub fn new(config: &BTreeMap<String, String>) -> Result<Theme, Vec<String>> {
+        let mut errors: Vec<String> = Vec::new();
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        let name = match config.get("name") {
+            Some(name) => name.as_str(),
+            None if no_color => "monochrome",
+            None => "default",
+        };
+        let mut theme = match Theme::builtin(name) {
+            Some(theme) => theme,
+            None => {
+                errors.push(format!("Unknown theme: {}", name));
+                Theme::default()
+            }
+        };
+        for (key, value) in config.iter().filter(|(k, _)| *k != "name") {
+            let style = match parse_style(value) {
+                Some(style) => style,
+                None => {
+                    errors.push(format!("Invalid style for {}: {}", key, value));
+                    continue;
+                }
+            };
+            match key.as_str() {
+                "header" => theme.header = style,
+                "selected" => theme.selected = style,
+                "marked" => theme.marked = style,
+                "favorite" => theme.favorite = style,
+                "missing" => theme.missing = style,
+                "failed" => theme.failed = style,
+                "status" => theme.status = style,
+                "error" => theme.error = style,
+                "prompt" => theme.prompt = style,
+                "search" => theme.search = style,
+                _ => errors.push(format!("Unknown theme style: {}", key)),
+            }
+        }
+        if errors.is_empty() {
+            Ok(theme)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Parse a style such as `yellow`, `bold`, `white on blue` or `#ff8800 italic`.
+/// The first color is the foreground and the color after `on` the background.
+fn parse_style(spec: &str) -> Option<Style> {
+    let mut style = Style::default();
+    let mut words = spec.split_whitespace();
+    while let Some(word) = words.next() {
+        if word == "on" {
+            style = style.bg(parse_color(words.next()?)?);
+            continue;
+        }
+        let modifier = match word {
+            "bold" => Some(Modifier::BOLD),
+            "dim" => Some(Modifier::DIM),
+            "italic" => Some(Modifier::ITALIC),
+            "underlined" => Some(Modifier::UNDERLINED),
+            "reversed" => Some(Modifier::REVERSED),
+            "crossed_out" => Some(Modifier::CROSSED_OUT),
+            "none" => Some(Modifier::empty()),
+            _ => None,
+        };
+        style = match modifier {
+            Some(modifier) => style.add_modifier(modifier),
+            None => style.fg(parse_color(word)?),
+        };
+    }
+    Some(style)
+}
+
+/// Parse a color name, `#rrggbb` value or 256-color index
+fn parse_color(name: &str) -> Option<Color> {
+    if let Some(hex) = name.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        return Some(Color::Rgb(
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ));
+    }
+    if let Ok(index) = name.parse::<u8>() {
+        return Some(Color::Indexed(index));
+    }
+    match name {
+        "reset" => Some(Color::Reset),
+        "black" => Some(Color::Black),
+        "red" => Some(Color::Red),
+        "green" => Some(Color::Green),
+        "yellow" => Some(Color::Yellow),
+        "blue" => Some(Color::Blue),
+        "magenta" => Some(Color::Magenta),
+        "cyan" => Some(Color::Cyan),
+        "gray" => Some(Color::Gray),
+        "dark_gray" => Some(Color::DarkGray),
+        "light_red" => Some(Color::LightRed),
+        "light_green" => Some(Color::LightGreen),
+        "light_yellow" => Some(Color::LightYellow),
+        "light_blue" => Some(Color::LightBlue),
+        "light_magenta" => Some(Color::LightMagenta),
+        "light_cyan" => Some(Color::LightCyan),
+        "white" => Some(Color::White),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_styles() {
+        assert_eq!(
+            Some(
+                Style::default()
+                    .fg(Color::White)
+                    .bg(Color::Blue)
+                    .add_modifier(Modifier::BOLD)
+            ),
+            parse_style("bold white on blue")
+        );
+        assert_eq!(
+            Some(
+                Style::default()
+                    .fg(Color::Rgb(0xff, 0x88, 0))
+                    .bg(Color::Indexed(236))
+            ),
+            parse_style("#ff8800 on 236")
+        );
+        assert_eq!(Some(Style::default()), parse_style(""));
+        assert_eq!(None, parse_style("white on"));
+        assert_eq!(None, parse_style("#fff"));
+        assert_eq!(None, parse_style("purple"));
+    }
+
+    #[test]
+    fn overrides_builtin_themes() {
+        let theme = Theme::new(&config(&[("name", "light"), ("marked", "green")])).unwrap();
+        assert_eq!(Style::default().fg(Color::Green), theme.marked);
+        assert_eq!(Theme::builtin("light").unwrap().header, theme.header);
+        assert_eq!(
+            Err(vec![
+                String::from("Unknown theme: solarized"),
+                String::from("Unknown theme style: border"),
+                String::from("Invalid style for header: purple"),
+            ]),
+            Theme::new(&config(&[
+                ("name", "solarized"),
+                ("header", "purple"),
+                ("border", "red"),
+            ]))
+            .map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::shelf::*;
+
+/// What items are grouped by in the tree view
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Group {
+    Author,
+    Genre,
+    Module,
+}
+
+impl Group {
+    /// Get the grouping after this one, going back to the flat table after
+    /// the last one
+    pub fn next(group: Option<Group>) -> Option<Group> {
+        match group {
+            None => Some(Group::Author),
+            Some(Group::Author) => Some(Group::Genre),
+            Some(Group::Genre) => Some(Group::Module),
+            Some(Group::Module) => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Group::Author => "author",
+            Group::Genre => "genre",
+            Group::Module => "module",
+        }
+    }
+
+    /// Get the groups an item belongs to. Items with several authors or genres
+    /// are in several groups.
+    fn names(&self, shelf: &Shelf, module: &str, code: &str) -> Vec<String> {
+        let item = match shelf.get_item(module, code) {
+            Some(item) => item,
+            None => return Vec::new(),
+        };
+        let (_, authors, genres) = item.export();
+        let (names, none) = match self {
+            Group::Author => (authors, "(no authors)"),
+            Group::Genre => (genres, "(no genres)"),
+            Group::Module => return vec![module.to_string()],
+        };
+        if names.is_empty() {
+            vec![none.to_string()]
+        } else {
+            names.iter().cloned().collect()
+        }
+    }
+}
+
+/// Line of the tree view
+pub enum Line {
+    /// Group with its name, number of items and whether it's expanded
+    Group(String, usize, bool),
+    /// Item, by its index in the table rows
+    Item(usize),
+}
+
+/// Build the lines of the tree, with groups sorted by name and their items in
+/// the order of `rows`. Every row is a line of its own when not grouping.
+pub fn build_lines(
+    shelf: &Shelf,
+    rows: &[Vec<String>],
+    group: Option<Group>,
+    expanded: &BTreeSet<String>,
+) -> Vec<Line> {
+    let group = match group {
+        Some(group) => group,
+        None => return (0..rows.len()).map(Line::Item).collect(),
+    };
+    let mut groups: BTreeMap<(String, String), Vec<usize>> = BTreeMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        for name in group.names(shelf, &row[3], &row[4]) {
+            groups
+                .entry((name.to_lowercase(), name))
+                .or_default()
+                .push(i);
+        }
+    }
+    let mut lines: Vec<Line> = Vec::new();
+    for ((_, name), items) in groups {
+        let open = expanded.contains(&name);
+        lines.push(Line::Group(name, items.len(), open));
+        if open {
+            lines.extend(items.into_iter().map(Line::Item));
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelf::test_util::{set, shelf};
+
+    fn describe(lines: &[Line]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| match line {
+                Line::Group(name, count, open) => format!("{} {} {}", name, count, open),
+                Line::Item(row) => row.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn groups_items() {
+        let mut shelf = shelf();
+        shelf.edit_item(Some("b"), Some("3"), None, Some("herbert"), None, false);
+        let rows: Vec<Vec<String>> = [("b", "3"), ("a", "2"), ("a", "1")]
+            .iter()
+            .map(|(m, c)| {
+                vec![
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    m.to_string(),
+                    c.to_string(),
+                ]
+            })
+            .collect();
+        let none = BTreeSet::new();
+        assert_eq!(
+            vec!["0", "1", "2"],
+            describe(&build_lines(&shelf, &rows, None, &none))
+        );
+        // names are sorted ignoring case, but groups keep their case
+        let expanded = set(&["Austen", "(no genres)"]);
+        assert_eq!(
+            vec!["Austen 1 true", "1", "Herbert 1 false", "herbert 1 false"],
+            describe(&build_lines(&shelf, &rows, Some(Group::Author), &expanded))
+        );
+        assert_eq!(
+            vec!["(no genres) 1 true", "1", "sf 2 false"],
+            describe(&build_lines(&shelf, &rows, Some(Group::Genre), &expanded))
+        );
+        assert_eq!(Some(Group::Module), Group::next(Some(Group::Genre)));
+        assert_eq!(None, Group::next(Some(Group::Module)));
+    }
+}
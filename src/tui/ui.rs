@@ -1,44 +1,723 @@
 use std::collections::{BTreeMap, BTreeSet};
-use termion::{event::Key, input::MouseTerminal, raw::IntoRawMode, screen::AlternateScreen};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use termion::{
+    event::{Event as TermEvent, Key, MouseButton, MouseEvent},
+    input::MouseTerminal,
+    raw::IntoRawMode,
+    screen::AlternateScreen,
+};
 use tui::{
-    backend::TermionBackend,
-    layout::{Constraint, Layout},
-    style::{Color, Modifier, Style},
-    widgets::{Block, Borders, Cell, Row, Table, TableState},
-    Terminal,
+    backend::{Backend, TermionBackend},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::{Span, Spans, Text},
+    widgets::{
+        Block, Borders, Cell, Clear, List, ListItem, ListState, Paragraph, Row, Table, TableState,
+        Wrap,
+    },
+    Frame, Terminal,
 };
 
+use crate::bibtex::{bibtex_entry, citation};
+use crate::clipboard::copy;
 use crate::config::*;
 use crate::fsio::*;
+use crate::history::History;
+use crate::items::{add_by_code, add_by_url, get_item_dir};
 use crate::module_handler::*;
 use crate::shelf::*;
+use crate::tui::column::{column_widths, Column};
+use crate::tui::cover::{Covers, Graphics};
+use crate::tui::details::Details;
 use crate::tui::event::{Event, Events};
+use crate::tui::input::Input;
+use crate::tui::keymap::{Action, Keymap, Lookup};
+use crate::tui::messages::Messages;
+use crate::tui::tab::Tab;
+use crate::tui::task::{Output, TaskEvent, Tasks};
+use crate::tui::theme::Theme;
+use crate::tui::tree::{build_lines, Group, Line};
+use crate::viewer::{open_item, OpenError};
+use regex::{Regex, RegexBuilder};
+use unicode_width::UnicodeWidthStr;
+
+/// Fields the index can be filtered by and edited
+#[derive(Clone, Copy)]
+enum Field {
+    Title,
+    Authors,
+    Genres,
+}
+
+impl Field {
+    fn name(&self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Authors => "authors",
+            Field::Genres => "genres",
+        }
+    }
+}
+
+/// Filters applied to the index. Filters on different fields stack.
+#[derive(Clone, Default)]
+struct Filter {
+    title: Option<String>,
+    authors: Option<String>,
+    genres: Option<String>,
+}
+
+impl Filter {
+    fn get(&self, field: Field) -> Option<&String> {
+        match field {
+            Field::Title => self.title.as_ref(),
+            Field::Authors => self.authors.as_ref(),
+            Field::Genres => self.genres.as_ref(),
+        }
+    }
+
+    /// Set the filter for a field. Empty values remove the filter.
+    fn set(&mut self, field: Field, value: &str) {
+        let value = if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        };
+        match field {
+            Field::Title => self.title = value,
+            Field::Authors => self.authors = value,
+            Field::Genres => self.genres = value,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.title.is_none() && self.authors.is_none() && self.genres.is_none()
+    }
+
+    /// Describe active filters, for example `title: ^The, authors: Bob`
+    fn describe(&self) -> String {
+        let mut result: Vec<String> = Vec::new();
+        for field in [Field::Title, Field::Authors, Field::Genres].iter() {
+            if let Some(value) = self.get(*field) {
+                result.push(format!("{}: {}", field.name(), value));
+            }
+        }
+        result.join(", ")
+    }
+}
 
+/// What keys are currently used for
+enum Mode {
+    Normal,
+    /// Showing the help, scrolled by some lines
+    Help(u16),
+    /// Showing the message log, scrolled by some lines
+    Messages(u16),
+    /// Showing background tasks, with one selected to cancel
+    Tasks(ListState),
+    /// Typing a filter, keeping the previous filter to restore on `Esc`
+    Filter(Field, Input, Filter),
+    /// Typing a search, which goes up if backward, keeping the previous
+    /// search and the selection to restore on `Esc`
+    Search(Input, bool, Option<Regex>, Option<usize>),
+    /// Editing a field of the selected item
+    Edit(Field, Input),
+    /// Asking whether to delete items, and their files
+    ConfirmDelete(Vec<(String, String)>),
+    /// Asking whether to save unsaved changes before quitting
+    ConfirmQuit,
+    /// Asking whether to discard unsaved changes to reload the index
+    ConfirmReload,
+    /// Typing the value of an edit applied to every marked item
+    Bulk(BulkEdit, Input),
+    /// Typing the URL of an item to add
+    AddUrl(Input),
+    /// Picking the module of an item to add by code
+    PickModule(ListState, Vec<String>),
+    /// Typing the code of an item to add with a module
+    AddCode(String, Input),
+}
+
+/// Edits applied to every marked item at once
+#[derive(Clone, Copy)]
+enum BulkEdit {
+    AddGenre,
+    RemoveGenre,
+    SetAuthors,
+}
+
+impl BulkEdit {
+    fn prompt(&self) -> &'static str {
+        match self {
+            BulkEdit::AddGenre => "Add genre: ",
+            BulkEdit::RemoveGenre => "Remove genre: ",
+            BulkEdit::SetAuthors => "Set authors: ",
+        }
+    }
+}
+
+/// State of downloads started from the TUI
+#[derive(Clone, Copy)]
+enum DownloadStatus {
+    Downloading,
+    Done,
+    Failed,
+}
+
+/// Lines scrolled by a turn of the mouse wheel
+const WHEEL_LINES: usize = 3;
+
+/// Longest time between the clicks of a double click
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// Frames of the spinner shown next to running downloads
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Items of a tab, with its own filters, sorting, grouping and selection
 struct IndexTable {
+    tab: Tab,
+    /// Number of items in the tab, before filters
+    total: usize,
+    /// Selected line
     state: TableState,
     items: Vec<Vec<String>>,
+    /// Lines shown, which are the rows or the groups of the tree view
+    lines: Vec<Line>,
+    group: Option<Group>,
+    /// Names of expanded groups
+    expanded: BTreeSet<String>,
+    filter: Filter,
+    /// Column rows are sorted by, in the order of the index if None
+    sort: Option<Column>,
+    ascending: bool,
+    downloads: BTreeMap<(String, String), DownloadStatus>,
+    /// Media types by module
+    media_types: BTreeMap<String, String>,
+    /// Items marked for bulk actions
+    marked: BTreeSet<(String, String)>,
+    /// Row where the range being marked started
+    anchor: Option<usize>,
+    /// Area inside the borders of the table when last drawn
+    area: Rect,
+    /// Start and width of each column
+    column_spans: Vec<(u16, u16)>,
+    /// First line shown
+    offset: usize,
+    /// When and on which line the last click was, to detect double clicks
+    last_click: Option<(Instant, usize)>,
+    /// Search highlighting matches, without hiding other rows
+    search: Option<Regex>,
+    /// Whether the search goes up, as when started with `?` by default
+    search_backward: bool,
 }
 
 impl IndexTable {
-    fn new(shelf: &Shelf) -> IndexTable {
-        IndexTable {
+    /// Create the table of a tab. Fails if the criteria of the tab have an
+    /// invalid title regex.
+    fn new(
+        shelf: &Shelf,
+        tab: Tab,
+        media_types: BTreeMap<String, String>,
+    ) -> Result<IndexTable, regex::Error> {
+        let mut table = IndexTable {
+            tab,
+            total: 0,
             state: TableState::default(),
-            items: index_to_table(
-                shelf.get_index(),
-                &shelf
-                    .search_item(None, None, None, None, None, false, false)
-                    .unwrap(),
-            ),
+            items: Vec::new(),
+            lines: Vec::new(),
+            group: None,
+            expanded: BTreeSet::new(),
+            filter: Filter::default(),
+            sort: None,
+            ascending: true,
+            downloads: BTreeMap::new(),
+            media_types,
+            marked: BTreeSet::new(),
+            anchor: None,
+            area: Rect::default(),
+            column_spans: Vec::new(),
+            offset: 0,
+            last_click: None,
+            search: None,
+            search_backward: false,
+        };
+        table.refresh(shelf)?;
+        Ok(table)
+    }
+
+    /// Rebuild rows from the shelf, applying the filter to the items of the
+    /// tab and keeping the selection in range. On invalid title regex, rows are
+    /// left as they are.
+    fn refresh(&mut self, shelf: &Shelf) -> Result<(), regex::Error> {
+        let tab_items = self.tab.search(shelf)?;
+        self.total = tab_items.len();
+        let targets: BTreeSet<(String, String)> = shelf
+            .search_item(
+                None,
+                self.filter.title.as_deref(),
+                self.filter.authors.as_deref(),
+                self.filter.genres.as_deref(),
+                None,
+                false,
+                false,
+            )?
+            .into_iter()
+            .filter(|key| tab_items.contains(key))
+            .collect();
+        self.items = index_to_table(shelf.get_index(), &targets);
+        self.sort_rows(shelf);
+        self.lines = build_lines(shelf, &self.items, self.group, &self.expanded);
+        self.anchor = None;
+        self.drop_missing_marks(shelf);
+        if self.lines.is_empty() {
+            self.state.select(None);
+        } else {
+            let i = self.state.selected().unwrap_or(0);
+            self.state.select(Some(i.min(self.lines.len() - 1)));
+        }
+        Ok(())
+    }
+
+    /// Get the text of a cell. `ticks` animates the spinner of running
+    /// downloads.
+    fn cell(&self, shelf: &Shelf, row: &[String], column: Column, ticks: usize) -> String {
+        match column {
+            Column::Favorite => {
+                if shelf.is_favorite(&row[3], &row[4]) {
+                    String::from("★")
+                } else {
+                    String::new()
+                }
+            }
+            Column::Title => row[0].clone(),
+            Column::Authors => row[1].clone(),
+            Column::Genres => row[2].clone(),
+            Column::Module => row[3].clone(),
+            Column::Code => row[4].clone(),
+            Column::Status => match self.downloads.get(&(row[3].clone(), row[4].clone())) {
+                Some(DownloadStatus::Downloading) => {
+                    format!("{} downloading", SPINNER[ticks % SPINNER.len()])
+                }
+                Some(DownloadStatus::Done) => String::from("downloaded"),
+                Some(DownloadStatus::Failed) => String::from("failed"),
+                None => String::new(),
+            },
+            Column::MediaType => self.media_types.get(&row[3]).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Sort by a column, or reverse the order if already sorted by it. The
+    /// selection stays on the same item.
+    fn sort_by(&mut self, shelf: &Shelf, column: Column) {
+        if self.sort == Some(column) {
+            self.ascending = !self.ascending;
+        } else {
+            self.sort = Some(column);
+            self.ascending = true;
+        }
+        let selected = self.selected_item();
+        self.sort_rows(shelf);
+        self.lines = build_lines(shelf, &self.items, self.group, &self.expanded);
+        if let Some((module, code)) = selected {
+            self.state.select(self.position(&module, &code));
+        }
+    }
+
+    /// Switch to the next grouping, starting with every group collapsed
+    fn cycle_group(&mut self, shelf: &Shelf) {
+        self.group = Group::next(self.group);
+        self.expanded.clear();
+        self.anchor = None;
+        let selected = self.selected_item();
+        self.lines = build_lines(shelf, &self.items, self.group, &self.expanded);
+        let i = match selected {
+            Some((module, code)) => self.position(&module, &code),
+            None => None,
+        };
+        self.state
+            .select(i.or(if self.lines.is_empty() { None } else { Some(0) }));
+    }
+
+    /// Expand or collapse the selected group, or toggle it if `expand` is
+    /// None. Collapsing an item collapses its group and selects it.
+    fn set_expanded(&mut self, shelf: &Shelf, expand: Option<bool>) {
+        let selected = match self.state.selected() {
+            Some(i) => i,
+            None => return,
+        };
+        let (i, name, open) = match &self.lines[selected] {
+            Line::Group(name, _, open) => (selected, name.clone(), *open),
+            Line::Item(_) if self.group.is_some() && expand == Some(false) => {
+                // the group is the closest one above
+                match self.lines[..selected]
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .find_map(|(i, line)| match line {
+                        Line::Group(name, _, open) => Some((i, name.clone(), *open)),
+                        Line::Item(_) => None,
+                    }) {
+                    Some(group) => group,
+                    None => return,
+                }
+            }
+            Line::Item(_) => return,
+        };
+        if expand.unwrap_or(!open) {
+            self.expanded.insert(name);
+        } else {
+            self.expanded.remove(&name);
+        }
+        self.anchor = None;
+        self.lines = build_lines(shelf, &self.items, self.group, &self.expanded);
+        self.state.select(Some(i));
+    }
+
+    /// Check if the title, authors or genres of a line match the search
+    fn is_match(&self, line: usize) -> bool {
+        match (&self.search, self.row(line)) {
+            (Some(search), Some(row)) => row[..3].iter().any(|cell| search.is_match(cell)),
+            _ => false,
+        }
+    }
+
+    /// Select the first line matching the search from `start`, going up when
+    /// `backward` and wrapping around. Returns whether a match was found.
+    fn find(&mut self, start: usize, backward: bool) -> bool {
+        let count = self.lines.len();
+        for i in 0..count {
+            let line = if backward {
+                (start + count - i) % count
+            } else {
+                (start + i) % count
+            };
+            if self.is_match(line) {
+                self.state.select(Some(line));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Go to the next match in the direction of the search, or the other way
+    /// when `reverse`. Returns whether a match was found.
+    fn find_next(&mut self, reverse: bool) -> bool {
+        let count = self.lines.len();
+        if count == 0 {
+            return false;
+        }
+        let backward = self.search_backward != reverse;
+        let start = match self.state.selected() {
+            Some(i) if backward => (i + count - 1) % count,
+            Some(i) => (i + 1) % count,
+            None => 0,
+        };
+        self.find(start, backward)
+    }
+
+    /// Get the height of a line, which is more than 1 for multi-line cells
+    fn line_height(&self, line: usize) -> u16 {
+        match self.row(line) {
+            Some(row) => {
+                row.iter()
+                    .map(|content| content.chars().filter(|c| *c == '\n').count())
+                    .max()
+                    .unwrap_or(0) as u16
+                    + 1
+            }
+            None => 1,
+        }
+    }
+
+    /// Remember where the table was drawn, and find the first line shown the
+    /// way tables scroll to the selection, so clicks can be matched to lines
+    fn set_layout(&mut self, area: Rect, column_widths: &[u16]) {
+        self.area = area;
+        let mut x = area.x;
+        self.column_spans = column_widths
+            .iter()
+            .map(|width| {
+                let span = (x, *width);
+                x = x.saturating_add(width + 1);
+                span
+            })
+            .collect();
+        if self.lines.is_empty() {
+            self.offset = 0;
+            return;
+        }
+        // the header and the margin below it take two lines
+        let max_height = area.height.saturating_sub(2);
+        let heights: Vec<u16> = (0..self.lines.len()).map(|i| self.line_height(i)).collect();
+        let mut start = self.offset.min(heights.len() - 1);
+        let mut end = start;
+        let mut height: u16 = 0;
+        for h in heights[start..].iter() {
+            if height + h > max_height {
+                break;
+            }
+            height += h;
+            end += 1;
+        }
+        let selected = self.state.selected().unwrap_or(0).min(heights.len() - 1);
+        while selected >= end {
+            height = height.saturating_add(heights[end]);
+            end += 1;
+            while height > max_height {
+                height = height.saturating_sub(heights[start]);
+                start += 1;
+            }
+        }
+        while selected < start {
+            start -= 1;
+            height = height.saturating_add(heights[start]);
+            while height > max_height {
+                end -= 1;
+                height = height.saturating_sub(heights[end]);
+            }
+        }
+        self.offset = start;
+    }
+
+    /// Handle a mouse event, returning the action it stands for, if any.
+    /// Clicks select lines, double clicks open items or toggle groups and
+    /// clicks on headers sort by their column.
+    fn mouse(&mut self, event: MouseEvent) -> Option<Action> {
+        let (button, x, y) = match event {
+            // termion counts from 1
+            MouseEvent::Press(button, x, y) => (button, x - 1, y - 1),
+            _ => return None,
+        };
+        match button {
+            MouseButton::WheelDown => {
+                if self
+                    .state
+                    .selected()
+                    .is_some_and(|i| i + 1 < self.lines.len())
+                {
+                    self.next(WHEEL_LINES);
+                }
+                None
+            }
+            MouseButton::WheelUp => {
+                if self.state.selected().is_some_and(|i| i > 0) {
+                    self.previous(WHEEL_LINES);
+                }
+                None
+            }
+            MouseButton::Left => {
+                let area = self.area;
+                if x < area.left() || x >= area.right() || y < area.top() || y >= area.bottom() {
+                    return None;
+                }
+                if y == area.top() {
+                    let column = self
+                        .column_spans
+                        .iter()
+                        .position(|(start, width)| *start <= x && x < start + width)?;
+                    return Some(Action::Sort(column + 1));
+                }
+                let mut top = area.top() + 2;
+                let mut line = None;
+                for i in self.offset..self.lines.len() {
+                    let height = self.line_height(i);
+                    if y < top {
+                        break;
+                    }
+                    if y < top + height {
+                        line = Some(i);
+                        break;
+                    }
+                    top += height;
+                }
+                let line = line?;
+                self.state.select(Some(line));
+                let double = self
+                    .last_click
+                    .is_some_and(|(time, l)| l == line && time.elapsed() < DOUBLE_CLICK);
+                if double {
+                    self.last_click = None;
+                    match self.lines[line] {
+                        Line::Group(..) => Some(Action::ToggleGroup),
+                        Line::Item(_) => Some(Action::Open),
+                    }
+                } else {
+                    self.last_click = Some((Instant::now(), line));
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the row of a line, if it's an item
+    fn row(&self, line: usize) -> Option<&Vec<String>> {
+        match self.lines.get(line)? {
+            Line::Item(i) => self.items.get(*i),
+            Line::Group(..) => None,
+        }
+    }
+
+    /// Get the first line of an item
+    fn position(&self, module: &str, code: &str) -> Option<usize> {
+        (0..self.lines.len()).find(|i| {
+            self.row(*i)
+                .is_some_and(|row| row[3] == module && row[4] == code)
+        })
+    }
+
+    /// Sort rows by the sort column, case-insensitively. Rows with the same
+    /// value keep the order of the index.
+    fn sort_rows(&mut self, shelf: &Shelf) {
+        let column = match self.sort {
+            Some(column) => column,
+            None => return,
+        };
+        let items = std::mem::take(&mut self.items);
+        let mut rows: Vec<(String, Vec<String>)> = items
+            .into_iter()
+            .map(|row| (self.cell(shelf, &row, column, 0).to_lowercase(), row))
+            .collect();
+        rows.sort_by(|(a, _), (b, _)| a.cmp(b));
+        if !self.ascending {
+            rows.reverse();
+        }
+        self.items = rows.into_iter().map(|(_, row)| row).collect();
+    }
+
+    /// Check if a line is marked, or in the range being marked
+    fn is_marked(&self, i: usize) -> bool {
+        let row = match self.row(i) {
+            Some(row) => row,
+            None => return false,
+        };
+        if self.marked.contains(&(row[3].clone(), row[4].clone())) {
+            return true;
+        }
+        match (self.anchor, self.state.selected()) {
+            (Some(anchor), Some(selected)) => {
+                anchor.min(selected) <= i && i <= anchor.max(selected)
+            }
+            _ => false,
+        }
+    }
+
+    /// Toggle the mark of the selected row
+    fn toggle_mark(&mut self) {
+        if let Some(key) = self.selected_item() {
+            if !self.marked.remove(&key) {
+                self.marked.insert(key);
+            }
+        }
+    }
+
+    /// Start marking a range at the selected row, or mark the range if one was
+    /// started
+    fn toggle_range(&mut self) {
+        if self.anchor.is_some() {
+            // targets include the range only while it has an anchor
+            self.marked.extend(self.targets());
+            self.anchor = None;
+        } else {
+            self.anchor = self.state.selected();
+        }
+    }
+
+    /// Mark every row shown, or unmark them if they already all are
+    fn toggle_all(&mut self) {
+        self.anchor = None;
+        let keys: Vec<(String, String)> = self
+            .items
+            .iter()
+            .map(|row| (row[3].clone(), row[4].clone()))
+            .collect();
+        if keys.iter().all(|k| self.marked.contains(k)) {
+            for key in keys.iter() {
+                self.marked.remove(key);
+            }
+        } else {
+            self.marked.extend(keys);
+        }
+    }
+
+    fn clear_marks(&mut self) {
+        self.marked.clear();
+        self.anchor = None;
+    }
+
+    /// Get the items actions apply to, which are the marked items or the
+    /// selected one if none are marked
+    fn targets(&self) -> Vec<(String, String)> {
+        let mut keys: BTreeSet<(String, String)> = self.marked.clone();
+        for i in 0..self.lines.len() {
+            if let Some(row) = self.row(i).filter(|_| self.is_marked(i)) {
+                keys.insert((row[3].clone(), row[4].clone()));
+            }
+        }
+        if keys.is_empty() {
+            keys.extend(self.selected_item());
         }
+        keys.into_iter().collect()
+    }
+
+    /// Get the module and code of the selected item
+    fn selected_item(&self) -> Option<(String, String)> {
+        let row = self.row(self.state.selected()?)?;
+        Some((row[3].clone(), row[4].clone()))
+    }
+
+    /// Rebuild the selected row from the shelf without sorting or filtering, so
+    /// the selection stays on the same item. Groups are rebuilt as the item may
+    /// have moved to others.
+    fn refresh_selected(&mut self, shelf: &Shelf) {
+        let i = match self.state.selected().and_then(|i| self.lines.get(i)) {
+            Some(Line::Item(i)) => *i,
+            _ => return,
+        };
+        let key = (self.items[i][3].clone(), self.items[i][4].clone());
+        let targets: BTreeSet<(String, String)> = [key.clone()].iter().cloned().collect();
+        if let Some(row) = index_to_table(shelf.get_index(), &targets).pop() {
+            self.items[i] = row;
+        }
+        if self.group.is_some() {
+            self.lines = build_lines(shelf, &self.items, self.group, &self.expanded);
+            if let Some(line) = self.position(&key.0, &key.1) {
+                self.state.select(Some(line));
+            } else if let Some(line) = self.state.selected() {
+                self.state
+                    .select(Some(line.min(self.lines.len().saturating_sub(1))));
+            }
+        }
+    }
+
+    /// Refresh the table when going back to its tab, as the shelf may have
+    /// changed in others, keeping the selection on the same item
+    fn reopen(&mut self, shelf: &Shelf) -> Result<(), regex::Error> {
+        let selected = self.selected_item();
+        self.refresh(shelf)?;
+        if let Some(i) = selected.and_then(|(m, c)| self.position(&m, &c)) {
+            self.state.select(Some(i));
+        }
+        Ok(())
+    }
+
+    /// Unmark items which left the shelf, as when undoing their addition
+    fn drop_missing_marks(&mut self, shelf: &Shelf) {
+        self.marked
+            .retain(|(module, code)| shelf.has_item(module, code));
     }
 
     pub fn next(&mut self, count: usize) {
+        if self.lines.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
-                if i >= self.items.len() - 1 {
+                if i >= self.lines.len() - 1 {
                     0
                 } else {
-                    i + count
+                    (i + count).min(self.lines.len() - 1)
                 }
             }
             None => 0,
@@ -47,12 +726,15 @@ impl IndexTable {
     }
 
     pub fn previous(&mut self, count: usize) {
+        if self.lines.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
-                    self.items.len() - 1
+                    self.lines.len() - 1
                 } else {
-                    i - count
+                    i.saturating_sub(count)
                 }
             }
             None => 0,
@@ -61,21 +743,93 @@ impl IndexTable {
     }
 
     pub fn goto_top(&mut self) {
-        self.state.select(Some(0));
+        if !self.lines.is_empty() {
+            self.state.select(Some(0));
+        }
     }
 
     pub fn goto_bottom(&mut self) {
-        self.state.select(Some(self.items.len() - 1));
+        if !self.lines.is_empty() {
+            self.state.select(Some(self.lines.len() - 1));
+        }
     }
 }
 
+/// What to do with the shelf after quitting
+pub enum Exit {
+    Save,
+    Discard,
+}
+
 pub struct TUI<'lt> {
     config: &'lt Config,
     shelf: &'lt mut Shelf,
     module_handler: &'lt ModuleHandler,
 }
 
-fn index_to_table<'a>(
+/// State of the TUI while it runs, besides the shelf
+struct State {
+    keymap: Keymap,
+    theme: Theme,
+    graphics: Graphics,
+    tables: Vec<IndexTable>,
+    /// index of the tab shown
+    current_tab: usize,
+    /// where the title of each tab starts and its width, if the tabs are shown
+    tab_spans: Vec<(u16, u16)>,
+    /// items with downloaded files, only checked again when they may change
+    downloaded: BTreeSet<(String, String)>,
+    mode: Mode,
+    messages: Messages,
+    error: String,
+    /// shelf as in the index file, to tell if there are unsaved changes
+    saved: Shelf,
+    /// shelf when the last reload started, to tell if items were edited
+    /// while the index was read
+    reload_base: Option<Shelf>,
+    /// whether to ask again to reload, once back in normal mode
+    ask_reload: bool,
+    /// what to do with the shelf, once the user quits
+    exit: Option<Exit>,
+    tasks: Tasks,
+    ticks: usize,
+    show_details: bool,
+    history: History,
+    /// details of the selected item, cleared when they may have changed
+    details: Details,
+    covers: Covers,
+    /// area of the cover in the details pane, if the item has one
+    cover_area: Option<Rect>,
+    term_height: u16,
+}
+
+/// Get the media types of the modules, by module
+fn media_types(module_handler: &ModuleHandler) -> BTreeMap<String, String> {
+    module_handler
+        .list_modules()
+        .into_iter()
+        .filter_map(|m| {
+            let media_type = module_handler.get_media_type(&m).ok()?;
+            Some((m, media_type))
+        })
+        .collect()
+}
+
+/// Set the download status of an item in every tab, or remove it if None
+fn set_download(tables: &mut [IndexTable], key: &(String, String), status: Option<DownloadStatus>) {
+    for table in tables.iter_mut() {
+        match status {
+            Some(status) => {
+                table.downloads.insert(key.clone(), status);
+            }
+            None => {
+                table.downloads.remove(key);
+            }
+        }
+    }
+}
+
+fn index_to_table(
     index: &BTreeMap<(String, String), Item>,
     targets: &BTreeSet<(String, String)>,
 ) -> Vec<Vec<String>> {
@@ -105,8 +859,284 @@ fn index_to_table<'a>(
     result
 }
 
+/// Get the value of a field of an item as edited in the TUI, with authors and
+/// genres comma-separated
+fn field_value(item: &Item, field: Field) -> String {
+    let (title, authors, genres) = item.export();
+    match field {
+        Field::Title => title.clone(),
+        Field::Authors => authors.iter().cloned().collect::<Vec<String>>().join(","),
+        Field::Genres => genres.iter().cloned().collect::<Vec<String>>().join(","),
+    }
+}
+
+/// Split text into lines with the parts matching a regex styled
+fn highlight(text: &str, regex: &Regex, style: Style) -> Text<'static> {
+    let lines: Vec<Spans> = text
+        .split('\n')
+        .map(|line| {
+            let mut spans: Vec<Span> = Vec::new();
+            let mut end = 0;
+            for m in regex.find_iter(line).filter(|m| !m.as_str().is_empty()) {
+                spans.push(Span::raw(line[end..m.start()].to_string()));
+                spans.push(Span::styled(m.as_str().to_string(), style));
+                end = m.end();
+            }
+            spans.push(Span::raw(line[end..].to_string()));
+            Spans::from(spans)
+        })
+        .collect();
+    Text::from(lines)
+}
+
+/// Get a rectangle of at most the given size centered in `area`
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Format a number of items, e.g. `1 item` or `3 items`
+fn items(count: usize) -> String {
+    if count == 1 {
+        String::from("1 item")
+    } else {
+        format!("{} items", count)
+    }
+}
+
+/// Get the items of the shelf with downloaded files, which are in a non-empty
+/// directory
+fn downloaded_items(shelf: &Shelf, data_dir: &Path) -> BTreeSet<(String, String)> {
+    shelf
+        .get_index()
+        .keys()
+        .filter(|(module, code)| {
+            std::fs::read_dir(get_item_dir(data_dir, module, code))
+                .is_ok_and(|mut entries| entries.next().is_some())
+        })
+        .cloned()
+        .collect()
+}
+
+/// Get the last non-empty line of a possibly multi-line message, which is the
+/// most relevant one for regex errors
+fn last_line(message: &str) -> &str {
+    message
+        .lines()
+        .map(str::trim)
+        .rev()
+        .find(|l| !l.is_empty())
+        .unwrap_or("")
+}
+
+/// Draw the popup of the mode, if it has one
+fn draw_popup<B: Backend>(frame: &mut Frame<B>, state: &mut State, area: Rect) {
+    let theme = &state.theme;
+    match &mut state.mode {
+        Mode::PickModule(list, modules) => {
+            let area = centered(area, 40, modules.len() as u16 + 2);
+            let items: Vec<ListItem> = modules.iter().map(|m| ListItem::new(m.as_str())).collect();
+            let widget = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title("Module"))
+                .highlight_style(theme.selected);
+            frame.render_widget(Clear, area);
+            frame.render_stateful_widget(widget, area, list);
+        }
+        Mode::Help(scroll) => {
+            let help = state.keymap.help();
+            let width = help.iter().map(|(k, _)| k.width()).max().unwrap_or(0);
+            let lines: Vec<Spans> = help
+                .iter()
+                .map(|(keys, description)| {
+                    Spans::from(vec![
+                        Span::styled(format!("{:width$}  ", keys, width = width), theme.header),
+                        Span::raw(description.as_str()),
+                    ])
+                })
+                .collect();
+            let area = centered(area, 60, help.len() as u16 + 2);
+            let block = Block::default()
+                .borders(Borders::ALL)
+                .title("Keys (j/k to scroll, any other key to close)");
+            frame.render_widget(Clear, area);
+            frame.render_widget(
+                Paragraph::new(lines).block(block).scroll((*scroll, 0)),
+                area,
+            );
+        }
+        Mode::Tasks(list) => {
+            let lines: Vec<ListItem> = state
+                .tasks
+                .running
+                .values()
+                .map(|task| {
+                    let mut line = format!(
+                        "{} {} {}/{}",
+                        task.bar(20),
+                        task.name,
+                        task.done,
+                        task.total
+                    );
+                    if task.is_cancelled() {
+                        line.push_str(" (cancelling)");
+                    }
+                    ListItem::new(line)
+                })
+                .collect();
+            let area = centered(area, area.width, lines.len() as u16 + 2);
+            let widget = List::new(lines)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title("Tasks (x to cancel, any other key to close)"),
+                )
+                .highlight_style(theme.selected);
+            frame.render_widget(Clear, area);
+            frame.render_stateful_widget(widget, area, list);
+        }
+        Mode::Messages(scroll) => {
+            let lines: Vec<Spans> = state
+                .messages
+                .log()
+                .iter()
+                .map(|m| Spans::from(m.as_str()))
+                .collect();
+            let area = centered(area, area.width, lines.len() as u16 + 2);
+            let block = Block::default()
+                .borders(Borders::ALL)
+                .title("Messages (j/k to scroll, any other key to close)");
+            frame.render_widget(Clear, area);
+            frame.render_widget(
+                Paragraph::new(lines).block(block).scroll((*scroll, 0)),
+                area,
+            );
+        }
+        _ => {}
+    }
+}
+
+/// Scroll the help or message log popup with a key, returning None to close it
+fn scroll_popup(scroll: u16, key: Key) -> Option<u16> {
+    match key {
+        Key::Down | Key::Char('j') => Some(scroll.saturating_add(1)),
+        Key::Up | Key::Char('k') => Some(scroll.saturating_sub(1)),
+        _ => None,
+    }
+}
+
+fn pick_module_key(state: &mut State, mut list: ListState, modules: Vec<String>, key: Key) {
+    match key {
+        Key::Char('\n') => {
+            if let Some(module) = list.selected().and_then(|i| modules.get(i)) {
+                state.mode = Mode::AddCode(module.clone(), Input::new(""));
+                return;
+            }
+        }
+        Key::Down | Key::Char('j') => {
+            let i = list.selected().map_or(0, |i| (i + 1) % modules.len());
+            list.select(Some(i));
+        }
+        Key::Up | Key::Char('k') => {
+            let i = list
+                .selected()
+                .map_or(0, |i| (i + modules.len() - 1) % modules.len());
+            list.select(Some(i));
+        }
+        Key::Esc | Key::Char('q') => return,
+        _ => {}
+    }
+    state.mode = Mode::PickModule(list, modules);
+}
+
+fn search_key(
+    state: &mut State,
+    mut input: Input,
+    backward: bool,
+    mut previous: Option<Regex>,
+    origin: Option<usize>,
+    key: Key,
+) {
+    let table = &mut state.tables[state.current_tab];
+    match key {
+        Key::Char('\n') => {
+            state.error.clear();
+            table.search_backward = backward;
+            if table.search.is_none() {
+                // search again for the previous pattern, like vim
+                table.search = previous;
+                if table.search.is_some() {
+                    table.find_next(false);
+                }
+            } else if !table.is_match(table.state.selected().unwrap_or(0)) {
+                state
+                    .messages
+                    .push(format!("Pattern not found: {}", input.text()));
+            }
+            return;
+        }
+        Key::Esc => {
+            table.search = previous.take();
+            table.state.select(origin);
+            state.error.clear();
+            return;
+        }
+        key => {
+            if input.handle_key(key) {
+                if input.text().is_empty() {
+                    table.search = None;
+                    table.state.select(origin);
+                    state.error.clear();
+                } else {
+                    match RegexBuilder::new(input.text())
+                        .case_insensitive(true)
+                        .build()
+                    {
+                        Ok(search) => {
+                            // look for matches from where the search started
+                            table.search = Some(search);
+                            let start = origin.unwrap_or(0);
+                            if !table.find(start, backward) {
+                                table.state.select(origin);
+                            }
+                            state.error.clear();
+                        }
+                        Err(e) => {
+                            state.error = format!("Invalid regex: {}", last_line(&e.to_string()))
+                        }
+                    }
+                }
+            }
+        }
+    }
+    state.mode = Mode::Search(input, backward, previous, origin);
+}
+
+fn tasks_key(state: &mut State, mut list: ListState, key: Key) {
+    let ids: Vec<usize> = state.tasks.running.keys().copied().collect();
+    let selected = list.selected().unwrap_or(0);
+    match key {
+        Key::Down | Key::Char('j') => {
+            list.select(Some((selected + 1).min(ids.len().saturating_sub(1))))
+        }
+        Key::Up | Key::Char('k') => list.select(Some(selected.saturating_sub(1))),
+        Key::Char('x') => {
+            if let Some(id) = ids.get(selected) {
+                state.tasks.cancel(*id);
+            }
+        }
+        _ => return,
+    }
+    state.mode = Mode::Tasks(list);
+}
+
 impl<'lt> TUI<'lt> {
-    pub fn new<'a>(
+    pub fn new(
         config: &'lt Config,
         shelf: &'lt mut Shelf,
         module_handler: &'lt ModuleHandler,
@@ -118,7 +1148,53 @@ impl<'lt> TUI<'lt> {
         }
     }
 
-    pub fn start(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Run the TUI until the user quits, returning whether to save the shelf
+    pub fn start(&mut self) -> Result<Exit, Box<dyn std::error::Error>> {
+        // Check bindings before taking over the terminal so errors are readable
+        let keymap = match Keymap::new(&self.config.keybindings) {
+            Ok(keymap) => keymap,
+            Err(errors) => {
+                return Err(format!("invalid keybindings:\n{}", errors.join("\n")).into())
+            }
+        };
+        let theme = match Theme::new(&self.config.theme) {
+            Ok(theme) => theme,
+            Err(errors) => return Err(format!("invalid theme:\n{}", errors.join("\n")).into()),
+        };
+        let graphics = match Graphics::new(&self.config.graphics) {
+            Some(graphics) => graphics,
+            None => return Err(format!("invalid graphics: {}", self.config.graphics).into()),
+        };
+        let tabs = if self.config.tabs.is_empty() {
+            Tab::defaults()
+        } else {
+            let mut tabs: Vec<Tab> = Vec::new();
+            let mut errors: Vec<String> = Vec::new();
+            for config in self.config.tabs.iter() {
+                match Tab::new(config) {
+                    Ok(tab) => tabs.push(tab),
+                    Err(e) => errors.extend(e),
+                }
+            }
+            if !errors.is_empty() {
+                return Err(format!("invalid tabs:\n{}", errors.join("\n")).into());
+            }
+            tabs
+        };
+        let media_types = media_types(self.module_handler);
+        let mut tables: Vec<IndexTable> = Vec::new();
+        for tab in tabs {
+            let name = tab.name.clone();
+            match IndexTable::new(self.shelf, tab, media_types.clone()) {
+                Ok(table) => tables.push(table),
+                Err(e) => {
+                    return Err(
+                        format!("invalid tab {}: {}", name, last_line(&e.to_string())).into(),
+                    )
+                }
+            }
+        }
+
         let stdout = std::io::stdout().into_raw_mode()?;
         let stdout = MouseTerminal::from(stdout);
         let stdout = AlternateScreen::from(stdout);
@@ -126,112 +1202,1312 @@ impl<'lt> TUI<'lt> {
         let mut terminal = Terminal::new(backend)?;
 
         let events = Events::new();
-        let mut table = IndexTable::new(&self.shelf);
-
-        let mut term_height: u16 = 1;
-        let mut running = true;
-        while running {
-            terminal.draw(|frame| {
-                term_height = frame.size().height;
-                let rects = Layout::default()
-                    .constraints([Constraint::Percentage(100)].as_ref())
-                    .margin(0)
-                    .split(frame.size());
-                let style_normal = Style::default();
-                let style_selected = Style::default().add_modifier(Modifier::REVERSED);
-                let header_cells = ["Title", "Authors", "Genres", "Module", "Code"]
-                    .iter()
-                    .map(|h| Cell::from(*h).style(Style::default().fg(Color::Red)));
-                let header = Row::new(header_cells)
-                    .style(style_normal)
-                    .height(1)
-                    .bottom_margin(1);
-                let rows = table.items.iter().map(|item| {
-                    let height = item
-                        .iter()
-                        .map(|content| content.chars().filter(|c| *c == '\n').count())
-                        .max()
-                        .unwrap_or(0)
-                        + 1;
-                    let cells = item.iter().map(|c| Cell::from(c.clone()));
-                    Row::new(cells).height(height as u16).bottom_margin(0)
-                });
-                let t = Table::new(rows)
-                    .header(header)
-                    .block(Block::default().borders(Borders::ALL))
-                    .highlight_style(style_selected)
-                    .widths(&[
-                        Constraint::Percentage(35),
-                        Constraint::Percentage(20),
-                        Constraint::Percentage(35),
-                        Constraint::Percentage(5),
-                        Constraint::Percentage(5),
-                    ]);
-                frame.render_stateful_widget(t, rects[0], &mut table.state);
-            })?;
+        let mut state = State {
+            keymap,
+            theme,
+            graphics,
+            tables,
+            current_tab: 0,
+            tab_spans: Vec::new(),
+            downloaded: downloaded_items(self.shelf, &self.config.data_dir),
+            mode: Mode::Normal,
+            messages: Messages::default(),
+            error: String::new(),
+            saved: self.shelf.clone(),
+            reload_base: None,
+            ask_reload: false,
+            exit: None,
+            tasks: Tasks::new(events.sender()),
+            ticks: 0,
+            show_details: false,
+            history: History::default(),
+            details: Details::new(events.sender()),
+            covers: Covers::new(events.sender()),
+            cover_area: None,
+            term_height: 1,
+        };
 
-            if let Event::Input(key) = events.next()? {
-                match key {
-                    Key::Char('q') => {
-                        running = false;
+        while state.exit.is_none() {
+            if state.show_details {
+                match state.tables[state.current_tab].selected_item() {
+                    Some((module, code)) => {
+                        state
+                            .details
+                            .load(self.config, self.module_handler, &module, &code)
                     }
-                    Key::Down | Key::Char('j') => {
-                        table.next(1);
-                    }
-                    Key::Up | Key::Char('k') => {
-                        table.previous(1);
-                    }
-                    Key::Ctrl('d') => {
-                        // move down 50%
-                        table.next(usize::from(term_height / 2) - 2);
-                    }
-                    Key::Ctrl('u') => {
-                        // move up 50%
-                        table.previous(usize::from(term_height / 2) - 2);
-                    }
-                    Key::Char('f') => {
-                        // filter
+                    None => state.details.clear(),
+                }
+            }
+
+            if state.ask_reload && matches!(state.mode, Mode::Normal) {
+                state.ask_reload = false;
+                state.mode = Mode::ConfirmReload;
+            }
+
+            let cover = state.details.get().and_then(|d| d.cover()).cloned();
+            terminal.draw(|frame| self.draw(frame, &mut state, cover.as_deref()))?;
+
+            match (&cover, state.cover_area) {
+                (Some(file), Some(area)) => state.covers.load(file, area, state.graphics),
+                _ => state.covers.clear(),
+            }
+            // Covers drawn with graphics protocols are hidden by popups
+            let popup = matches!(
+                state.mode,
+                Mode::Help(_) | Mode::Messages(_) | Mode::Tasks(_) | Mode::PickModule(..)
+            );
+            if state
+                .covers
+                .show(terminal.backend_mut(), state.graphics, !popup)?
+            {
+                terminal.clear()?;
+                continue;
+            }
+
+            let result = match events.next()? {
+                Event::Input(TermEvent::Key(key)) => self.handle_key(&mut state, key),
+                Event::Input(TermEvent::Mouse(mouse)) => self.handle_mouse(&mut state, mouse),
+                Event::Input(TermEvent::Unsupported(_)) => Ok(()),
+                Event::Tick => {
+                    state.ticks = state.ticks.wrapping_add(1);
+                    Ok(())
+                }
+                Event::Cover(id, cover) => {
+                    state.covers.receive(id, cover);
+                    Ok(())
+                }
+                Event::Details(id, fetched) => {
+                    state.details.receive(id, fetched);
+                    Ok(())
+                }
+                Event::Task(event) => self.handle_task(&mut state, event),
+            };
+            // the table keeps its rows when its filter can't be applied
+            if let Err(e) = result {
+                state
+                    .messages
+                    .push(format!("Invalid regex: {}", last_line(&e.to_string())));
+            }
+            // other tabs are only refreshed when going back to them, so their
+            // marks must not outlive items removed meanwhile
+            for table in state.tables.iter_mut() {
+                table.drop_missing_marks(self.shelf);
+            }
+        }
+
+        state.covers.clear();
+        state
+            .covers
+            .show(terminal.backend_mut(), state.graphics, false)?;
+        Ok(state.exit.unwrap_or(Exit::Discard))
+    }
+
+    /// Draw the tabs, the table of the current tab and its details pane, then
+    /// the popup and input or status line of the mode
+    fn draw<B: Backend>(&self, frame: &mut Frame<B>, state: &mut State, cover: Option<&Path>) {
+        state.term_height = frame.size().height;
+        state.cover_area = None;
+        let theme = &state.theme;
+        let mut rects = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+            .margin(0)
+            .split(frame.size());
+        // Tabs are listed above the table when there are several
+        state.tab_spans.clear();
+        if state.tables.len() > 1 && rects[0].height > 1 {
+            let mut spans: Vec<Span> = Vec::new();
+            let mut x = rects[0].x;
+            for (i, table) in state.tables.iter().enumerate() {
+                if i > 0 {
+                    spans.push(Span::raw("│"));
+                    x += 1;
+                }
+                let title = format!(" {} {} ", i + 1, table.tab.name);
+                let width = title.width() as u16;
+                state.tab_spans.push((x, width));
+                x = x.saturating_add(width);
+                if i == state.current_tab {
+                    spans.push(Span::styled(title, theme.selected));
+                } else {
+                    spans.push(Span::raw(title));
+                }
+            }
+            let bar = Rect {
+                height: 1,
+                ..rects[0]
+            };
+            frame.render_widget(Paragraph::new(Spans::from(spans)), bar);
+            rects[0].y += 1;
+            rects[0].height -= 1;
+        }
+        // Put details on the side of wide terminals and below otherwise
+        let (table_rect, details_rect) = if state.show_details {
+            let direction = if rects[0].width >= 100 {
+                Direction::Horizontal
+            } else {
+                Direction::Vertical
+            };
+            let parts = Layout::default()
+                .direction(direction)
+                .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+                .split(rects[0]);
+            (parts[0], Some(parts[1]))
+        } else {
+            (rects[0], None)
+        };
+        let shelf: &Shelf = self.shelf;
+        let ticks = state.ticks;
+        let downloaded = &state.downloaded;
+        let table = &mut state.tables[state.current_tab];
+        let style_normal = Style::default();
+        let columns = &self.config.columns;
+        let header_cells = columns.iter().map(|(column, _)| {
+            let mut header = column.header().to_string();
+            if table.sort == Some(*column) {
+                header.push_str(if table.ascending { " ▲" } else { " ▼" });
+            }
+            Cell::from(header).style(theme.header)
+        });
+        let header = Row::new(header_cells)
+            .style(style_normal)
+            .height(1)
+            .bottom_margin(1);
+        // Groups are named in the title column, or the first one
+        let label_column = columns
+            .iter()
+            .position(|(c, _)| *c == Column::Title)
+            .unwrap_or(0);
+        let rows = table.lines.iter().enumerate().map(|(i, line)| {
+            let item = match line {
+                Line::Item(row) => &table.items[*row],
+                Line::Group(name, count, open) => {
+                    let label = format!("{} {} ({})", if *open { "▾" } else { "▸" }, name, count);
+                    let cells = (0..columns.len()).map(|c| {
+                        if c == label_column {
+                            Cell::from(label.clone())
+                        } else {
+                            Cell::from("")
+                        }
+                    });
+                    return Row::new(cells).style(theme.header);
+                }
+            };
+            let height = table.line_height(i);
+            let cells = columns.iter().map(|(column, _)| {
+                let mut text = table.cell(shelf, item, *column, ticks);
+                if table.group.is_some() && *column == Column::Title {
+                    text.insert_str(0, "  ");
+                }
+                match (&table.search, column) {
+                    (Some(search), Column::Title)
+                    | (Some(search), Column::Authors)
+                    | (Some(search), Column::Genres) => {
+                        Cell::from(highlight(&text, search, theme.search))
                     }
-                    Key::Char('e') => {
-                        // edit
+                    _ => Cell::from(text),
+                }
+            });
+            let key = (item[3].clone(), item[4].clone());
+            let mut style = style_normal;
+            if shelf.is_favorite(&key.0, &key.1) {
+                style = style.patch(theme.favorite);
+            }
+            if !downloaded.contains(&key) {
+                style = style.patch(theme.missing);
+            }
+            if let Some(DownloadStatus::Failed) = table.downloads.get(&key) {
+                style = style.patch(theme.failed);
+            }
+            if table.is_marked(i) {
+                style = style.patch(theme.marked);
+            }
+            Row::new(cells).style(style).height(height).bottom_margin(0)
+        });
+        let inner = Block::default().borders(Borders::ALL).inner(table_rect);
+        let column_widths = column_widths(columns, inner.width);
+        let widths: Vec<Constraint> = column_widths
+            .iter()
+            .map(|w| Constraint::Length(*w))
+            .collect();
+        let block = match table.group {
+            Some(group) => Block::default()
+                .borders(Borders::ALL)
+                .title(format!("By {}", group.name())),
+            None => Block::default().borders(Borders::ALL),
+        };
+        let t = Table::new(rows)
+            .header(header)
+            .block(block)
+            .highlight_style(theme.selected)
+            .widths(&widths);
+        frame.render_stateful_widget(t, table_rect, &mut table.state);
+        table.set_layout(inner, &column_widths);
+        if let Some(rect) = details_rect {
+            let lines = match state.details.get() {
+                Some(details) => details.lines(shelf, theme),
+                None => Vec::new(),
+            };
+            let block = Block::default().borders(Borders::ALL).title("Details");
+            let mut text_rect = block.inner(rect);
+            frame.render_widget(block, rect);
+            // The cover takes the upper half of the pane
+            if let (Some(file), true) = (cover, state.graphics != Graphics::None) {
+                let parts = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Percentage(50), Constraint::Min(0)].as_ref())
+                    .split(text_rect);
+                let lines = state.covers.lines(file, parts[0], state.graphics);
+                frame.render_widget(Paragraph::new(lines), parts[0]);
+                state.cover_area = Some(parts[0]);
+                text_rect = parts[1];
+            }
+            let pane = Paragraph::new(lines).wrap(Wrap { trim: false });
+            frame.render_widget(pane, text_rect);
+        }
+
+        draw_popup(frame, state, rects[0]);
+        self.draw_status(frame, state, rects[1]);
+    }
+
+    /// Draw the input of the mode, its question, or the status line with
+    /// messages, tasks and the filters of the table
+    fn draw_status<B: Backend>(&self, frame: &mut Frame<B>, state: &State, area: Rect) {
+        let theme = &state.theme;
+        let table = &state.tables[state.current_tab];
+        // prompt and input of modes typing something
+        let input = match &state.mode {
+            Mode::Edit(field, input) => Some((format!("Edit {}: ", field.name()), input)),
+            Mode::AddUrl(input) => Some((String::from("Add URL (Tab to pick a module): "), input)),
+            Mode::AddCode(module, input) => Some((format!("Add {} code: ", module), input)),
+            Mode::Bulk(edit, input) => Some((
+                format!("{}: {}", items(table.targets().len()), edit.prompt()),
+                input,
+            )),
+            Mode::Filter(field, input, _) => Some((format!("Filter {}: ", field.name()), input)),
+            Mode::Search(input, backward, _, _) => {
+                let action = if *backward {
+                    Action::SearchBackward
+                } else {
+                    Action::Search
+                };
+                Some((state.keymap.keys(action).unwrap_or_default(), input))
+            }
+            _ => None,
+        };
+        if let Some((prompt, input)) = input {
+            let cursor = area.x + prompt.width() as u16 + input.cursor_width();
+            let line = format!("{}{}", prompt, input.text());
+            // Show errors after the input so they're visible while typing
+            let spans = match &state.mode {
+                Mode::Filter(..) | Mode::Search(..) => Spans::from(vec![
+                    Span::raw(line),
+                    Span::raw("  "),
+                    Span::styled(state.error.as_str(), theme.error),
+                ]),
+                _ => Spans::from(line),
+            };
+            frame.render_widget(Paragraph::new(spans), area);
+            frame.set_cursor(cursor, area.y);
+            return;
+        }
+        let question = match &state.mode {
+            Mode::ConfirmDelete(keys) => {
+                let what = match &keys[..] {
+                    [(module, code)] => format!("{} {}", module, code),
+                    _ => items(keys.len()),
+                };
+                Some(format!(
+                    "Delete {}? y: from index, f: also delete files (not undoable), n: cancel",
+                    what
+                ))
+            }
+            Mode::ConfirmQuit => Some(String::from(
+                "Save changes before quitting? y: save, n: discard, Esc: cancel",
+            )),
+            Mode::ConfirmReload => Some(String::from(
+                "Discard unsaved changes and reload? y: reload, n: cancel",
+            )),
+            _ => None,
+        };
+        if let Some(question) = question {
+            frame.render_widget(Paragraph::new(question).style(theme.prompt), area);
+            return;
+        }
+
+        let mut spans: Vec<Span> = Vec::new();
+        if *self.shelf != state.saved {
+            spans.push(Span::styled(
+                "[modified] ",
+                theme.status.add_modifier(Modifier::BOLD),
+            ));
+        }
+        let marked = table.targets().len();
+        if !table.marked.is_empty() || table.anchor.is_some() {
+            spans.push(Span::styled(format!("[{} marked] ", marked), theme.marked));
+        }
+        let pending = state.keymap.pending();
+        if !pending.is_empty() {
+            spans.push(Span::styled(
+                format!("{} ", pending),
+                theme.status.add_modifier(Modifier::BOLD),
+            ));
+        }
+        // Show the oldest task, and how many others there are
+        let tasks = &state.tasks;
+        if let Some(task) = tasks.running.values().next() {
+            let mut text = if task.total > 1 {
+                format!(
+                    "[{} {} {}/{}",
+                    task.name,
+                    task.bar(10),
+                    task.done,
+                    task.total
+                )
+            } else {
+                format!("[{} {}", task.name, SPINNER[state.ticks % SPINNER.len()])
+            };
+            if tasks.running.len() > 1 {
+                text.push_str(&format!(" +{}", tasks.running.len() - 1));
+            }
+            text.push_str("] ");
+            spans.push(Span::styled(text, theme.prompt));
+        }
+        spans.push(Span::raw(state.messages.current()));
+        // Filter and counts on the right
+        let total = table.total;
+        let mut summary = if table.filter.is_empty() {
+            items(total)
+        } else {
+            format!(
+                "{} | {} of {}",
+                table.filter.describe(),
+                table.items.len(),
+                items(total)
+            )
+        };
+        summary.insert(0, ' ');
+        let width = (summary.width() as u16).min(area.width);
+        let parts = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(0), Constraint::Length(width)].as_ref())
+            .split(area);
+        frame.render_widget(
+            Paragraph::new(Spans::from(spans)).style(theme.status),
+            parts[0],
+        );
+        frame.render_widget(Paragraph::new(summary).style(theme.status), parts[1]);
+    }
+
+    /// Handle progress and output of background tasks
+    fn handle_task(&mut self, state: &mut State, event: TaskEvent) -> Result<(), regex::Error> {
+        if let Some(task) = state.tasks.update(&event) {
+            if task.is_cancelled() {
+                state.messages.push(format!("Cancelled {}", task.name));
+            }
+        }
+        let output = match event {
+            TaskEvent::Output(_, output) => output,
+            _ => return Ok(()),
+        };
+        let messages = &mut state.messages;
+        match output {
+            Output::Added(what, Ok(new_shelf)) => {
+                // Items are fetched into their own shelf, merged into
+                // self.shelf when received
+                let keys: Vec<(String, String)> = new_shelf.get_index().keys().cloned().collect();
+                if keys.iter().all(|(m, c)| self.shelf.has_item(m, c)) {
+                    messages.push(format!("{} is already indexed", what));
+                    return Ok(());
+                }
+                let before = self.shelf.snapshot(&keys);
+                self.shelf.import(&new_shelf);
+                state
+                    .downloaded
+                    .extend(downloaded_items(&new_shelf, &self.config.data_dir));
+                state
+                    .history
+                    .record(&format!("adding {}", what), before, self.shelf);
+                let table = &mut state.tables[state.current_tab];
+                table.refresh(self.shelf)?;
+                if let Some((module, code)) = keys.first() {
+                    let i = table.position(module, code);
+                    if i.is_some() {
+                        table.state.select(i);
                     }
-                    Key::Char('F') => {
-                        // toggle favorite
+                }
+                messages.push(format!("Added {}", what));
+            }
+            Output::Added(what, Err(e)) => messages.push(format!("Error adding {}: {}", what, e)),
+            Output::Downloaded(key, result) => {
+                let (module, code): &(String, String) = &key;
+                match result {
+                    Ok(()) => {
+                        messages.push(format!("Downloaded {} {}", module, code));
+                        state.downloaded.insert(key.clone());
+                        set_download(&mut state.tables, &key, Some(DownloadStatus::Done));
                     }
-                    Key::Char('D') => {
-                        // delete
+                    Err(e) => {
+                        messages.push(format!("Error downloading {} {}: {}", module, code, e));
+                        set_download(&mut state.tables, &key, Some(DownloadStatus::Failed));
                     }
-                    Key::Char('d') => {
-                        // download
+                }
+                state.details.clear();
+            }
+            Output::Skipped(key) => {
+                set_download(&mut state.tables, &key, None);
+            }
+            Output::Loaded(Ok(_))
+                if state
+                    .reload_base
+                    .take()
+                    .is_some_and(|base| *self.shelf != base) =>
+            {
+                // replacing the shelf would lose these edits
+                messages.push(String::from("Items were edited while reloading"));
+                state.ask_reload = true;
+            }
+            Output::Loaded(Ok(shelf)) => {
+                *self.shelf = shelf;
+                state.saved = self.shelf.clone();
+                state.downloaded = downloaded_items(self.shelf, &self.config.data_dir);
+                state.history.clear();
+                state.details.clear();
+                state.tables[state.current_tab].refresh(self.shelf)?;
+                messages.push(format!("Reloaded {}", items(self.shelf.get_index().len())));
+            }
+            Output::Loaded(Err(e)) => messages.push(format!("Error reading index file: {}", e)),
+        }
+        Ok(())
+    }
+
+    /// Handle a key: bindings in normal mode, and the keys themselves in other
+    /// modes. Modes go back to normal unless their handler keeps them.
+    fn handle_key(&mut self, state: &mut State, key: Key) -> Result<(), regex::Error> {
+        match std::mem::replace(&mut state.mode, Mode::Normal) {
+            Mode::Normal => match state.keymap.lookup(key) {
+                Lookup::Action(action) => return self.handle_action(state, action),
+                Lookup::Pending | Lookup::Unbound => {}
+            },
+            Mode::ConfirmQuit => match key {
+                Key::Char('y') => state.exit = Some(Exit::Save),
+                Key::Char('n') => state.exit = Some(Exit::Discard),
+                _ => {}
+            },
+            Mode::ConfirmReload => {
+                if key == Key::Char('y') {
+                    self.reload(state);
+                }
+            }
+            Mode::ConfirmDelete(keys) => return self.confirm_delete(state, keys, key),
+            Mode::Edit(field, input) => self.edit_key(state, field, input, key),
+            Mode::AddUrl(input) => self.add_url_key(state, input, key),
+            Mode::PickModule(list, modules) => pick_module_key(state, list, modules, key),
+            Mode::AddCode(module, input) => self.add_code_key(state, module, input, key),
+            Mode::Bulk(edit, input) => return self.bulk_key(state, edit, input, key),
+            Mode::Filter(field, input, previous) => {
+                return self.filter_key(state, field, input, previous, key)
+            }
+            Mode::Search(input, backward, previous, origin) => {
+                search_key(state, input, backward, previous, origin, key)
+            }
+            Mode::Tasks(list) => tasks_key(state, list, key),
+            Mode::Help(scroll) => {
+                if let Some(scroll) = scroll_popup(scroll, key) {
+                    state.mode = Mode::Help(scroll);
+                }
+            }
+            Mode::Messages(scroll) => {
+                if let Some(scroll) = scroll_popup(scroll, key) {
+                    state.mode = Mode::Messages(scroll);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle the mouse: clicks and the wheel on the table and tabs in normal
+    /// mode, and the wheel in popups which scroll
+    fn handle_mouse(&mut self, state: &mut State, mouse: MouseEvent) -> Result<(), regex::Error> {
+        let action = match &mut state.mode {
+            // the tabs are on the first line, and termion counts from 1
+            Mode::Normal => match mouse {
+                MouseEvent::Press(MouseButton::Left, x, 1) if !state.tab_spans.is_empty() => state
+                    .tab_spans
+                    .iter()
+                    .position(|(start, width)| *start < x && x <= start + width)
+                    .map(|i| Action::Tab(i + 1)),
+                _ => state.tables[state.current_tab].mouse(mouse),
+            },
+            Mode::Help(scroll) | Mode::Messages(scroll) => {
+                match mouse {
+                    MouseEvent::Press(MouseButton::WheelDown, _, _) => {
+                        *scroll = scroll.saturating_add(WHEEL_LINES as u16)
                     }
-                    Key::Home => {
-                        table.goto_top();
+                    MouseEvent::Press(MouseButton::WheelUp, _, _) => {
+                        *scroll = scroll.saturating_sub(WHEEL_LINES as u16)
                     }
-                    Key::End => {
-                        table.goto_bottom();
+                    _ => {}
+                }
+                None
+            }
+            _ => None,
+        };
+        match action {
+            Some(action) => self.handle_action(state, action),
+            None => Ok(()),
+        }
+    }
+
+    /// Run an action of normal mode
+    fn handle_action(&mut self, state: &mut State, action: Action) -> Result<(), regex::Error> {
+        let modified = *self.shelf != state.saved;
+        let messages = &mut state.messages;
+        let table = &mut state.tables[state.current_tab];
+        match action {
+            Action::Quit => {
+                if modified {
+                    state.mode = Mode::ConfirmQuit;
+                } else {
+                    state.exit = Some(Exit::Discard);
+                }
+            }
+            Action::Down => {
+                table.next(1);
+            }
+            Action::Up => {
+                table.previous(1);
+            }
+            Action::HalfPageDown => {
+                // move down 50%
+                table.next(usize::from(
+                    (state.term_height / 2).saturating_sub(2).max(1),
+                ));
+            }
+            Action::HalfPageUp => {
+                // move up 50%
+                table.previous(usize::from(
+                    (state.term_height / 2).saturating_sub(2).max(1),
+                ));
+            }
+            Action::ToggleFavorite => {
+                // toggle favorite, making all items favorites unless they
+                // already all are
+                let keys = table.targets();
+                if keys.is_empty() {
+                    return Ok(());
+                }
+                let favorite = !keys.iter().all(|(m, c)| self.shelf.is_favorite(m, c));
+                let before = self.shelf.snapshot(&keys);
+                let mut count = 0;
+                for (module, code) in keys.iter() {
+                    if self.shelf.set_favorite(module, code, favorite) {
+                        count += 1;
                     }
-                    Key::Char('y') => {
-                        // yank item to clipboard
+                }
+                let description = if favorite {
+                    format!("adding {} to favorites", items(count))
+                } else {
+                    format!("removing {} from favorites", items(count))
+                };
+                state.history.record(&description, before, self.shelf);
+                if keys.len() > 1 {
+                    messages.push(if favorite {
+                        format!("Added {} to favorites", items(count))
+                    } else {
+                        format!("Removed {} from favorites", items(count))
+                    });
+                }
+                table.clear_marks();
+            }
+            Action::Delete => {
+                // delete
+                let keys = table.targets();
+                if !keys.is_empty() {
+                    state.mode = Mode::ConfirmDelete(keys);
+                }
+            }
+            Action::ToggleMark => {
+                table.toggle_mark();
+                table.next(1);
+            }
+            Action::MarkRange => {
+                table.toggle_range();
+            }
+            Action::MarkAll => {
+                table.toggle_all();
+            }
+            Action::Cancel if !table.marked.is_empty() || table.anchor.is_some() => {
+                table.clear_marks();
+            }
+            Action::Cancel if table.search.is_some() => {
+                // stop highlighting the search
+                table.search = None;
+            }
+            Action::Cancel if !table.filter.is_empty() => {
+                // clear filters
+                table.filter = Filter::default();
+                table.refresh(self.shelf)?;
+            }
+            Action::Add => {
+                // add by URL, or by module and code
+                state.mode = Mode::AddUrl(Input::new(""));
+            }
+            Action::AddGenre => {
+                state.mode = Mode::Bulk(BulkEdit::AddGenre, Input::new(""));
+            }
+            Action::RemoveGenre => {
+                state.mode = Mode::Bulk(BulkEdit::RemoveGenre, Input::new(""));
+            }
+            Action::SetAuthors => {
+                state.mode = Mode::Bulk(BulkEdit::SetAuthors, Input::new(""));
+            }
+            Action::Undo => match state.history.undo(self.shelf) {
+                Some(description) => {
+                    table.refresh(self.shelf)?;
+                    state.details.clear();
+                    messages.push(format!("Undid {}", description));
+                }
+                None => messages.push(String::from("Nothing to undo")),
+            },
+            Action::Redo => match state.history.redo(self.shelf) {
+                Some(description) => {
+                    table.refresh(self.shelf)?;
+                    state.details.clear();
+                    messages.push(format!("Redid {}", description));
+                }
+                None => messages.push(String::from("Nothing to redo")),
+            },
+            Action::Download => self.download(state),
+            Action::Top => {
+                table.goto_top();
+            }
+            Action::Bottom => {
+                table.goto_bottom();
+            }
+            Action::ToggleDetails => {
+                state.show_details = !state.show_details;
+                state.details.clear();
+            }
+            Action::Group => {
+                table.cycle_group(self.shelf);
+                messages.push(match table.group {
+                    Some(group) => format!("Grouped by {}", group.name()),
+                    None => String::from("Ungrouped"),
+                });
+            }
+            Action::Expand => {
+                table.set_expanded(self.shelf, Some(true));
+            }
+            Action::Collapse => {
+                table.set_expanded(self.shelf, Some(false));
+            }
+            Action::ToggleGroup => {
+                table.set_expanded(self.shelf, None);
+            }
+            Action::Open => {
+                // open item
+                if let Some((module, code)) = table.selected_item() {
+                    messages.push(self.open_item(&module, &code));
+                }
+            }
+            Action::Reload => {
+                if modified {
+                    state.mode = Mode::ConfirmReload;
+                } else {
+                    self.reload(state);
+                }
+            }
+            Action::Write => {
+                // write
+                match write_shelf(self.shelf, &self.config.index_file) {
+                    Ok(()) => {
+                        state.saved = self.shelf.clone();
+                        messages.push(format!(
+                            "Wrote {} to {}",
+                            items(self.shelf.get_index().len()),
+                            self.config.index_file.display()
+                        ));
                     }
-                    Key::Char('o') => {
-                        // open item
+                    Err(e) => messages.push(format!("Error writing index file: {:?}", e)),
+                }
+            }
+            Action::Cancel => {}
+            Action::FilterTitle | Action::FilterAuthors | Action::FilterGenres => {
+                let field = match action {
+                    Action::FilterTitle => Field::Title,
+                    Action::FilterAuthors => Field::Authors,
+                    _ => Field::Genres,
+                };
+                let value = table.filter.get(field).cloned().unwrap_or_default();
+                state.mode = Mode::Filter(field, Input::new(&value), table.filter.clone());
+            }
+            Action::Search | Action::SearchBackward => {
+                let backward = action == Action::SearchBackward;
+                let previous = table.search.take();
+                state.mode =
+                    Mode::Search(Input::new(""), backward, previous, table.state.selected());
+            }
+            Action::SearchNext | Action::SearchPrevious => match &table.search {
+                Some(search) => {
+                    let pattern = search.as_str().to_string();
+                    if !table.find_next(action == Action::SearchPrevious) {
+                        messages.push(format!("Pattern not found: {}", pattern));
                     }
-                    Key::Char('r') => {
-                        // Reload index
-                        *self.shelf = load_shelf(&self.config.index_file);
-                        table = IndexTable::new(&self.shelf);
+                }
+                None => messages.push(String::from("No search")),
+            },
+            Action::EditTitle | Action::EditAuthors | Action::EditGenres => {
+                let field = match action {
+                    Action::EditTitle => Field::Title,
+                    Action::EditAuthors => Field::Authors,
+                    _ => Field::Genres,
+                };
+                if let Some((module, code)) = table.selected_item() {
+                    if let Some(item) = self.shelf.get_item(&module, &code) {
+                        let value = field_value(item, field);
+                        state.mode = Mode::Edit(field, Input::new(&value));
                     }
-                    Key::Char('w') => {
-                        // write
-                        save_shelf(&self.shelf, &self.config.index_file);
+                }
+            }
+            Action::Sort(n) => {
+                // sort by the nth column
+                if let Some((column, _)) = self.config.columns.get(n - 1) {
+                    table.sort_by(self.shelf, *column);
+                }
+            }
+            Action::YankItem | Action::YankUrl | Action::YankCitation | Action::YankBibtex => {
+                if let Some((module, code)) = table.selected_item() {
+                    messages.push(self.yank_item(&module, &code, action));
+                }
+            }
+            Action::Messages => {
+                // start at the end, above the status bar and borders
+                let visible = usize::from(state.term_height.saturating_sub(3));
+                let scroll = messages.log().len().saturating_sub(visible);
+                state.mode = Mode::Messages(scroll as u16);
+            }
+            Action::Tasks => {
+                if state.tasks.running.is_empty() {
+                    messages.push(String::from("No tasks running"));
+                } else {
+                    let mut list = ListState::default();
+                    list.select(Some(0));
+                    state.mode = Mode::Tasks(list);
+                }
+            }
+            Action::Tab(_) | Action::NextTab | Action::PreviousTab => {
+                let count = state.tables.len();
+                let tab = match action {
+                    Action::Tab(n) => n - 1,
+                    Action::NextTab => (state.current_tab + 1) % count,
+                    _ => (state.current_tab + count - 1) % count,
+                };
+                if tab >= count {
+                    messages.push(format!("No tab {}", tab + 1));
+                } else if tab != state.current_tab {
+                    state.current_tab = tab;
+                    // edits made in other tabs show up in this one
+                    state.tables[tab].reopen(self.shelf)?;
+                    state.details.clear();
+                }
+            }
+            Action::Help => {
+                state.mode = Mode::Help(0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Download the marked or selected items one after the other in the
+    /// background, skipping those already downloading
+    fn download(&self, state: &mut State) {
+        let table = &mut state.tables[state.current_tab];
+        let keys: Vec<(String, String)> = table
+            .targets()
+            .into_iter()
+            .filter(|k| !matches!(table.downloads.get(k), Some(DownloadStatus::Downloading)))
+            .collect();
+        if keys.is_empty() {
+            return;
+        }
+        table.clear_marks();
+        for key in keys.iter() {
+            set_download(&mut state.tables, key, Some(DownloadStatus::Downloading));
+        }
+        let name = match &keys[..] {
+            [(module, code)] => format!("downloading {} {}", module, code),
+            _ => format!("downloading {}", items(keys.len())),
+        };
+        let data_dir = self.config.data_dir.clone();
+        let module_handler = self.module_handler.clone();
+        state.tasks.spawn(name, keys.len(), move |progress| {
+            for (i, (module, code)) in keys.into_iter().enumerate() {
+                if progress.is_cancelled() {
+                    progress.send(Output::Skipped((module, code)));
+                    continue;
+                }
+                let dest_dir = get_item_dir(&data_dir, &module, &code);
+                let result = module_handler
+                    .download(&module, &code, &dest_dir)
+                    .map(|_output| ())
+                    .map_err(|e| format!("{:?}", e));
+                // the TUI may have exited already
+                if !progress.send(Output::Downloaded((module, code), result)) {
+                    break;
+                }
+                progress.step(i + 1);
+            }
+        });
+    }
+
+    /// Reload the index in the background, keeping filters
+    fn reload(&self, state: &mut State) {
+        let index_file = self.config.index_file.clone();
+        state.reload_base = Some(self.shelf.clone());
+        state
+            .tasks
+            .spawn(String::from("reloading index"), 1, move |progress| {
+                let result = read_shelf(&index_file).map_err(|e| format!("{:?}", e));
+                if !progress.is_cancelled() {
+                    progress.send(Output::Loaded(result));
+                }
+            });
+    }
+
+    fn confirm_delete(
+        &mut self,
+        state: &mut State,
+        keys: Vec<(String, String)>,
+        key: Key,
+    ) -> Result<(), regex::Error> {
+        if !matches!(key, Key::Char('y') | Key::Char('f')) {
+            state.messages.hide();
+            return Ok(());
+        }
+        let before = self.shelf.snapshot(&keys);
+        for (module, code) in keys.iter() {
+            self.shelf.remove_item(module, code);
+        }
+        state.history.record(
+            &format!("deletion of {}", items(keys.len())),
+            before,
+            self.shelf,
+        );
+        let table = &mut state.tables[state.current_tab];
+        table.clear_marks();
+        table.refresh(self.shelf)?;
+        state.details.clear();
+        state.messages.push(match &keys[..] {
+            [(module, code)] => format!("Deleted {} {}", module, code),
+            _ => format!("Deleted {}", items(keys.len())),
+        });
+        if key == Key::Char('f') {
+            for (module, code) in keys.iter() {
+                state.downloaded.remove(&(module.clone(), code.clone()));
+                let item_dir = get_item_dir(&self.config.data_dir, module, code);
+                if item_dir.exists() {
+                    if let Err(e) = std::fs::remove_dir_all(&item_dir) {
+                        state.messages.push(format!("Error deleting files: {}", e));
                     }
-                    _ => {}
                 }
             }
         }
+        Ok(())
+    }
+
+    fn edit_key(&mut self, state: &mut State, field: Field, mut input: Input, key: Key) {
+        match key {
+            Key::Char('\n') => {
+                let table = &mut state.tables[state.current_tab];
+                if let Some((module, code)) = table.selected_item() {
+                    let value = Some(input.text());
+                    let (title, authors, genres) = match field {
+                        Field::Title => (value, None, None),
+                        Field::Authors => (None, value, None),
+                        Field::Genres => (None, None, value),
+                    };
+                    let key = (module.clone(), code.clone());
+                    let before = self.shelf.snapshot(&[key]);
+                    self.shelf
+                        .edit_item(Some(&module), Some(&code), title, authors, genres, false);
+                    state.history.record(
+                        &format!("edit of {} of {} {}", field.name(), module, code),
+                        before,
+                        self.shelf,
+                    );
+                    table.refresh_selected(self.shelf);
+                }
+            }
+            Key::Esc => {}
+            key => {
+                input.handle_key(key);
+                state.mode = Mode::Edit(field, input);
+            }
+        }
+    }
 
+    fn add_url_key(&self, state: &mut State, mut input: Input, key: Key) {
+        match key {
+            Key::Char('\n') => {
+                let url = input.text().trim().to_string();
+                if url.is_empty() {
+                    return;
+                }
+                let module_handler = self.module_handler.clone();
+                state
+                    .tasks
+                    .spawn(format!("adding {}", url), 1, move |progress| {
+                        let mut new_shelf = Shelf::new();
+                        let result = add_by_url(&mut new_shelf, &module_handler, &url, None, false)
+                            .map(|()| new_shelf)
+                            .map_err(|e| format!("{:?}", e));
+                        if !progress.is_cancelled() {
+                            progress.send(Output::Added(url, result));
+                        }
+                    });
+            }
+            Key::Char('\t') => {
+                let modules: Vec<String> = self.module_handler.list_modules().into_iter().collect();
+                if modules.is_empty() {
+                    state.messages.push(String::from("No modules available"));
+                } else {
+                    let mut list = ListState::default();
+                    list.select(Some(0));
+                    state.mode = Mode::PickModule(list, modules);
+                }
+            }
+            Key::Esc => {}
+            _ => {
+                input.handle_key(key);
+                state.mode = Mode::AddUrl(input);
+            }
+        }
+    }
+
+    fn add_code_key(&self, state: &mut State, module: String, mut input: Input, key: Key) {
+        match key {
+            Key::Char('\n') => {
+                let code = input.text().trim().to_string();
+                if code.is_empty() {
+                    return;
+                }
+                let module_handler = self.module_handler.clone();
+                let what = format!("{} {}", module, code);
+                state
+                    .tasks
+                    .spawn(format!("adding {}", what), 1, move |progress| {
+                        let mut new_shelf = Shelf::new();
+                        let result = add_by_code(
+                            &mut new_shelf,
+                            &module_handler,
+                            &module,
+                            &code,
+                            None,
+                            false,
+                        )
+                        .map(|()| new_shelf)
+                        .map_err(|e| format!("{:?}", e));
+                        if !progress.is_cancelled() {
+                            progress.send(Output::Added(what, result));
+                        }
+                    });
+            }
+            Key::Esc => {}
+            _ => {
+                input.handle_key(key);
+                state.mode = Mode::AddCode(module, input);
+            }
+        }
+    }
+
+    fn bulk_key(
+        &mut self,
+        state: &mut State,
+        edit: BulkEdit,
+        mut input: Input,
+        key: Key,
+    ) -> Result<(), regex::Error> {
+        match key {
+            Key::Char('\n') => {}
+            Key::Esc => return Ok(()),
+            _ => {
+                input.handle_key(key);
+                state.mode = Mode::Bulk(edit, input);
+                return Ok(());
+            }
+        }
+        let value = input.text().trim().to_string();
+        if value.is_empty() {
+            return Ok(());
+        }
+        let table = &mut state.tables[state.current_tab];
+        let keys = table.targets();
+        let before = self.shelf.snapshot(&keys);
+        let mut count = 0;
+        for (module, code) in keys.iter() {
+            let changed = match edit {
+                BulkEdit::AddGenre => self.shelf.add_genre(module, code, &value),
+                BulkEdit::RemoveGenre => self.shelf.remove_genre(module, code, &value),
+                BulkEdit::SetAuthors => {
+                    let before = self.shelf.get_item(module, code).cloned();
+                    self.shelf
+                        .edit_item(Some(module), Some(code), None, Some(&value), None, false);
+                    before.as_ref() != self.shelf.get_item(module, code)
+                }
+            };
+            if changed {
+                count += 1;
+            }
+        }
+        state.messages.push(match edit {
+            BulkEdit::AddGenre => format!("Added genre {} to {}", value, items(count)),
+            BulkEdit::RemoveGenre => format!("Removed genre {} from {}", value, items(count)),
+            BulkEdit::SetAuthors => format!("Set authors of {}", items(count)),
+        });
+        let description = match edit {
+            BulkEdit::AddGenre => format!("adding genre {}", value),
+            BulkEdit::RemoveGenre => format!("removing genre {}", value),
+            BulkEdit::SetAuthors => format!("setting authors to {}", value),
+        };
+        state.history.record(&description, before, self.shelf);
+        table.clear_marks();
+        // keep rows in place, like when editing a single item
+        let selected = table.state.selected();
+        table.refresh(self.shelf)?;
+        table
+            .state
+            .select(selected.filter(|i| *i < table.lines.len()));
+        state.details.clear();
+        Ok(())
+    }
+
+    fn filter_key(
+        &self,
+        state: &mut State,
+        field: Field,
+        mut input: Input,
+        previous: Filter,
+        key: Key,
+    ) -> Result<(), regex::Error> {
+        let table = &mut state.tables[state.current_tab];
+        match key {
+            Key::Char('\n') => {
+                // keep the last valid filter
+                state.error.clear();
+            }
+            Key::Esc => {
+                // restore filter from before editing
+                table.filter = previous;
+                table.refresh(self.shelf)?;
+                state.error.clear();
+            }
+            key => {
+                if input.handle_key(key) {
+                    let mut filter = table.filter.clone();
+                    filter.set(field, input.text());
+                    let old_filter = std::mem::replace(&mut table.filter, filter);
+                    if let Err(e) = table.refresh(self.shelf) {
+                        // keep the last valid filter and show why
+                        table.filter = old_filter;
+                        state.error = format!("Invalid regex: {}", last_line(&e.to_string()));
+                    } else {
+                        state.error.clear();
+                    }
+                }
+                state.mode = Mode::Filter(field, input, previous);
+            }
+        }
         Ok(())
     }
 
-    fn open_item(&self, module: &String, code: &String) {}
+    /// Copy the module and code, URL, citation or BibTeX entry of an item to the
+    /// clipboard, returning the message to show in the status line
+    fn yank_item(&self, module: &str, code: &str, action: Action) -> String {
+        let item = match self.shelf.get_item(module, code) {
+            Some(item) => item,
+            None => return String::new(),
+        };
+        let (what, text) = match action {
+            Action::YankItem => ("item", format!("{} {}", module, code)),
+            Action::YankUrl => match self.module_handler.derive_url(module, code) {
+                Ok(url) => ("URL", url),
+                Err(e) => return format!("Error getting URL of {} {}: {:?}", module, code, e),
+            },
+            Action::YankCitation => {
+                let url = self.module_handler.derive_url(module, code).ok();
+                ("citation", citation(item, url.as_deref()))
+            }
+            Action::YankBibtex => ("BibTeX entry", bibtex_entry(module, code, item)),
+            _ => return String::new(),
+        };
+        match copy(self.config, &text) {
+            Ok(()) => format!("Copied {} of {} {}", what, module, code),
+            Err(e) => format!("Error copying {}: {:?}", what, e),
+        }
+    }
+
+    /// Open an item, returning the message to show in the status line
+    fn open_item(&self, module: &str, code: &str) -> String {
+        match open_item(self.config, self.module_handler, module, code) {
+            Ok(()) => format!("Opened {} {}", module, code),
+            Err(OpenError::NotDownloaded(_)) => {
+                format!(
+                    "{} {} is not downloaded, press d to download it",
+                    module, code
+                )
+            }
+            Err(e) => format!("Error opening {} {}: {:?}", module, code, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shelf::test_util::shelf;
+
+    fn table(shelf: &Shelf, tab: usize) -> IndexTable {
+        let tab = Tab::defaults().remove(tab);
+        IndexTable::new(shelf, tab, BTreeMap::new()).unwrap()
+    }
+
+    fn codes(keys: &[(String, String)]) -> Vec<&str> {
+        keys.iter().map(|(_, code)| code.as_str()).collect()
+    }
+
+    #[test]
+    fn filters_items_of_the_tab() {
+        let mut shelf = shelf();
+        shelf.set_favorite("b", "3", true);
+        let mut favorites = table(&shelf, 1);
+        assert_eq!(2, favorites.total);
+        favorites.filter.set(Field::Authors, "Herbert");
+        favorites.refresh(&shelf).unwrap();
+        assert_eq!(
+            vec![vec!["Dune Messiah", "Herbert", "sf", "b", "3"]],
+            favorites.items
+        );
+        assert_eq!(2, favorites.total);
+
+        // invalid regexes leave rows as they are
+        favorites.filter.set(Field::Title, "(");
+        assert!(favorites.refresh(&shelf).is_err());
+        assert_eq!(1, favorites.items.len());
+    }
+
+    #[test]
+    fn marks_targets() {
+        let shelf = shelf();
+        let mut table = table(&shelf, 0);
+        // rows are sorted by title: Dune, Dune Messiah, Emma
+        assert_eq!(vec!["1"], codes(&table.targets()));
+        table.toggle_range();
+        table.goto_bottom();
+        assert_eq!(vec!["1", "2", "3"], codes(&table.targets()));
+        table.toggle_range();
+        table.goto_top();
+        table.toggle_mark();
+        assert_eq!(vec!["2", "3"], codes(&table.targets()));
+        table.toggle_all();
+        assert_eq!(3, table.marked.len());
+        table.toggle_all();
+        assert_eq!(vec!["1"], codes(&table.targets()));
+    }
+
+    #[test]
+    fn drops_marks_of_removed_items() {
+        let mut shelf = shelf();
+        let mut all = table(&shelf, 0);
+        let mut favorites = table(&shelf, 1);
+        all.toggle_all();
+        favorites.toggle_all();
+        shelf.remove_item("b", "3");
+        shelf.remove_item("a", "2");
+        all.refresh(&shelf).unwrap();
+        assert_eq!(vec!["1"], codes(&all.targets()));
+        // tabs which are not shown keep their rows until reopened
+        favorites.drop_missing_marks(&shelf);
+        assert!(favorites.marked.is_empty());
+    }
+
+    #[test]
+    fn searches_and_sorts() {
+        let shelf = shelf();
+        let mut table = table(&shelf, 0);
+        table.search = Some(
+            RegexBuilder::new("dune")
+                .case_insensitive(true)
+                .build()
+                .unwrap(),
+        );
+        assert!(table.find_next(false));
+        assert_eq!(Some(1), table.state.selected());
+        // searches wrap around, and go up when started backward
+        assert!(table.find_next(false));
+        assert_eq!(Some(0), table.state.selected());
+        table.search_backward = true;
+        assert!(table.find_next(false));
+        assert_eq!(Some(1), table.state.selected());
+
+        // the selection stays on the item
+        table.sort_by(&shelf, Column::Authors);
+        assert_eq!(Some(2), table.state.selected());
+        table.sort_by(&shelf, Column::Authors);
+        assert!(!table.ascending);
+        assert_eq!(
+            vec!["Dune Messiah", "Dune", "Emma"],
+            table
+                .items
+                .iter()
+                .map(|r| r[0].as_str())
+                .collect::<Vec<&str>>()
+        );
+        assert_eq!(Some(0), table.state.selected());
+    }
+
+    #[test]
+    fn finds_downloaded_items() {
+        let shelf = shelf();
+        let data_dir =
+            std::env::temp_dir().join(format!("bookshelf-test-downloaded-{}", std::process::id()));
+        std::fs::create_dir_all(get_item_dir(&data_dir, "b", "3")).unwrap();
+        let item_dir = get_item_dir(&data_dir, "a", "1");
+        std::fs::create_dir_all(&item_dir).unwrap();
+        std::fs::write(item_dir.join("1.jpg"), b"").unwrap();
+        let downloaded = downloaded_items(&shelf, &data_dir);
+        std::fs::remove_dir_all(&data_dir).unwrap();
+        // empty directories are left by failed downloads
+        assert_eq!(
+            vec![(String::from("a"), String::from("1"))],
+            downloaded.into_iter().collect::<Vec<_>>()
+        );
+    }
+    #[test]
+    fn formats_messages() {
+        assert_eq!("1 item", items(1));
+        assert_eq!("0 items", items(0));
+        assert_eq!(
+            "unclosed group",
+            last_line("regex parse error:\n    (\n    ^\nunclosed group\n")
+        );
+        assert_eq!(
+            Rect::new(12, 6, 6, 8),
+            centered(Rect::new(10, 6, 10, 8), 6, 20)
+        );
+        let text = highlight(
+            "a dune\nDune",
+            &Regex::new("(?i)dune").unwrap(),
+            Style::default(),
+        );
+        assert_eq!(2, text.lines.len());
+        assert_eq!(
+            vec!["a ", "dune", ""],
+            text.lines[0]
+                .0
+                .iter()
+                .map(|s| s.content.as_ref())
+                .collect::<Vec<&str>>()
+        );
+    }
 }
@@ -0,0 +1,194 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::config::*;
+use crate::fsio::{list_files, mime_type};
+use crate::items::get_item_dir;
+use crate::module_handler::*;
+
+/// Command used when no viewer is configured for a media type
+const FALLBACK_VIEWER: &str = "xdg-open";
+
+#[derive(Debug)]
+pub enum OpenError {
+    /// The item directory is missing or has no files
+    NotDownloaded(PathBuf),
+    /// The viewer command is empty or has an unterminated quote
+    InvalidViewer(String),
+    IoError(std::io::Error),
+}
+
+/// Open a downloaded item with the viewer configured for its media type,
+/// falling back to `xdg-open`. The viewer runs detached from bookshelf, with a
+/// thread waiting for it to exit so it doesn't linger as a zombie.
+pub fn open_item(
+    config: &Config,
+    module_handler: &ModuleHandler,
+    module: &str,
+    code: &str,
+) -> Result<(), OpenError> {
+    let item_dir = get_item_dir(&config.data_dir, module, code);
+    let files = list_files(&item_dir);
+    if files.is_empty() {
+        return Err(OpenError::NotDownloaded(item_dir));
+    }
+
+    // Modules that aren't installed (e.g. local files) fall back to the
+    // extension of the first file
+    let media_type = match module_handler.get_media_type(module) {
+        Ok(media_type) => media_type,
+        Err(_e) => files[0]
+            .extension()
+            .and_then(std::ffi::OsStr::to_str)
+            .unwrap_or("")
+            .to_lowercase(),
+    };
+    let viewer = config.viewers.get(&media_type);
+    let targets = get_targets(&item_dir, &files, viewer.is_some());
+
+    let words = match split_command(viewer.map_or(FALLBACK_VIEWER, String::as_str)) {
+        Some(words) if !words.is_empty() => words,
+        _ => return Err(OpenError::InvalidViewer(media_type)),
+    };
+    let (program, words) = (&words[0], &words[1..]);
+    // `{}` marks where the paths go, otherwise they are appended
+    let mut args: Vec<&std::ffi::OsStr> = Vec::new();
+    let mut placed = false;
+    for word in words {
+        if word == "{}" {
+            args.extend(targets.iter().map(|t| t.as_os_str()));
+            placed = true;
+        } else {
+            args.push(word.as_ref());
+        }
+    }
+    if !placed {
+        args.extend(targets.iter().map(|t| t.as_os_str()));
+    }
+    match Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+            Ok(())
+        }
+        Err(e) => Err(OpenError::IoError(e)),
+    }
+}
+
+/// Split a command into words like a shell does, with single quotes, double
+/// quotes and backslashes but no expansions. Returns None if a quote isn't
+/// closed.
+pub fn split_command(command: &str) -> Option<Vec<String>> {
+    let mut words: Vec<String> = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        // backslashes only escape what's special in double quotes
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '\\' | '$' | '`') => word.push(c),
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let c = chars.next().unwrap_or('\\');
+                word.get_or_insert_with(String::new).push(c);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Some(words)
+}
+
+/// Get the paths to pass to the viewer. Single files are opened directly and
+/// directories of images are opened as a whole, or from their first image when
+/// the fallback viewer, which takes a single path, is used. Other directories
+/// are opened as is.
+fn get_targets(item_dir: &Path, files: &[PathBuf], configured: bool) -> Vec<PathBuf> {
+    if files.len() == 1 {
+        return files.to_vec();
+    }
+    if files.iter().all(|f| mime_type(f).starts_with("image/")) {
+        if configured {
+            files.to_vec()
+        } else {
+            vec![files[0].clone()]
+        }
+    } else {
+        vec![item_dir.to_path_buf()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(command: &str) -> Option<Vec<String>> {
+        split_command(command)
+    }
+
+    #[test]
+    fn splits_like_a_shell() {
+        assert_eq!(Some(vec![String::from("zathura")]), split("  zathura "));
+        assert_eq!(
+            Some(
+                vec!["mpv", "--title=My Book", "{}", "a b", ""]
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            ),
+            split(r#"mpv "--title=My Book" {} a\ b ''"#)
+        );
+        assert_eq!(
+            Some(vec![String::from(r#"say "hi" \n $x"#)]),
+            split(r#""say \"hi\" \n \$x""#)
+        );
+        assert_eq!(Some(vec![String::from("it's")]), split(r#"it"'"s"#));
+        assert_eq!(Some(Vec::new()), split(""));
+        assert_eq!(None, split("viewer 'unterminated"));
+        assert_eq!(None, split("viewer \"unterminated"));
+    }
+
+    #[test]
+    fn targets() {
+        let dir = Path::new("/data/mod/1");
+        let images = vec![dir.join("1.jpg"), dir.join("2.png")];
+        assert_eq!(images, get_targets(dir, &images, true));
+        assert_eq!(images[..1].to_vec(), get_targets(dir, &images, false));
+        let mixed = vec![dir.join("a.epub"), dir.join("b.jpg")];
+        assert_eq!(vec![dir.to_path_buf()], get_targets(dir, &mixed, true));
+        assert_eq!(mixed[..1].to_vec(), get_targets(dir, &mixed[..1], false));
+    }
+}